@@ -48,6 +48,11 @@ pub trait WindowRenderer {
     fn is_active(&self) -> bool;
     fn set_size(&mut self, width: u32, height: u32);
     fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F);
+
+    /// Sets the color that the window is cleared to before the scene is drawn. Takes effect from the next frame.
+    ///
+    /// Renderers which don't support a configurable base color ignore this.
+    fn set_base_color(&mut self, _color: Color) {}
 }
 
 /// Abstraction for rendering a scene to an image buffer
@@ -64,6 +69,11 @@ pub trait ImageRenderer {
         vec: &mut Vec<u8>,
     );
     fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F, buffer: &mut [u8]);
+
+    /// Sets the color that the image is cleared to before the scene is drawn. Takes effect from the next render.
+    ///
+    /// Renderers which don't support a configurable base color ignore this.
+    fn set_base_color(&mut self, _color: Color) {}
 }
 
 /// Draw a scene to a buffer using an `ImageRenderer`
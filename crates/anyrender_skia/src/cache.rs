@@ -1,6 +1,7 @@
 use std::hash::{Hash, Hasher};
 
 use hashbrown::{Equivalent, HashMap};
+use skia_safe::{GlyphId, Point};
 
 struct CacheEntry<T> {
     resource: T,
@@ -41,6 +42,14 @@ where
         }
     }
 
+    pub(crate) fn len(&self) -> usize {
+        self.resources.len()
+    }
+
+    pub(crate) fn set_max_age(&mut self, max_age: usize) {
+        self.max_age = max_age;
+    }
+
     pub(crate) fn next_gen(&mut self) {
         self.resources.retain(|_, entry| {
             self.current_generation.wrapping_sub(entry.generation) < self.max_age
@@ -169,3 +178,67 @@ impl Equivalent<FontCacheKey> for FontCacheKeyBorrowed<'_> {
             && self.normalized_coords == key.normalized_coords
     }
 }
+
+/// The key of a glyph run's text blob. Positions are relative to the first glyph.
+///
+/// Only the font and glyph ids are hashed: runs with the same glyphs but different positions are
+/// rare, and are told apart by the (full) equality check.
+pub(crate) struct TextBlobCacheKeyBorrowed<'a> {
+    pub(crate) font: FontCacheKeyBorrowed<'a>,
+    pub(crate) glyph_ids: &'a [GlyphId],
+    pub(crate) positions: &'a [Point],
+}
+
+impl<'a> Hash for TextBlobCacheKeyBorrowed<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.font.hash(state);
+        self.glyph_ids.hash(state);
+    }
+}
+
+impl<'a> TextBlobCacheKeyBorrowed<'a> {
+    pub(crate) fn to_owned_key(&self) -> TextBlobCacheKey {
+        TextBlobCacheKey {
+            font: FontCacheKey {
+                typeface_id: self.font.typeface_id,
+                typeface_index: self.font.typeface_index,
+                normalized_coords: self.font.normalized_coords.to_vec(),
+                font_size: self.font.font_size,
+                hint: self.font.hint,
+            },
+            glyph_ids: self.glyph_ids.to_vec(),
+            positions: self
+                .positions
+                .iter()
+                .map(|position| (position.x.to_bits(), position.y.to_bits()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+pub(crate) struct TextBlobCacheKey {
+    pub(crate) font: FontCacheKey,
+    pub(crate) glyph_ids: Vec<GlyphId>,
+    pub(crate) positions: Vec<(u32, u32)>,
+}
+
+impl Hash for TextBlobCacheKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.font.hash(state);
+        self.glyph_ids.hash(state);
+    }
+}
+
+impl Equivalent<TextBlobCacheKey> for TextBlobCacheKeyBorrowed<'_> {
+    fn equivalent(&self, key: &TextBlobCacheKey) -> bool {
+        self.font.equivalent(&key.font)
+            && self.glyph_ids == key.glyph_ids
+            && self.positions.len() == key.positions.len()
+            && self
+                .positions
+                .iter()
+                .zip(&key.positions)
+                .all(|(position, &(x, y))| position.x.to_bits() == x && position.y.to_bits() == y)
+    }
+}
@@ -1,13 +1,65 @@
-use anyrender::ImageRenderer;
+use anyrender::{ImageRenderer, PaintScene};
 use debug_timer::debug_timer;
-use skia_safe::{AlphaType, Color, ColorType, ImageInfo, SurfaceProps, graphics, surfaces};
+use peniko::{Color, ImageAlphaType};
+use skia_safe::{AlphaType, ColorType, ImageInfo, SurfaceProps, surfaces};
 
-use crate::{SkiaScenePainter, scene::SkiaSceneCache};
+use crate::{
+    SkiaCacheStats, SkiaGlobalCacheLimits, SkiaRendererOptions, SkiaScenePainter,
+    scene::SkiaSceneCache,
+};
 
 pub struct SkiaImageRenderer {
     image_info: ImageInfo,
     surface_props: SurfaceProps,
     scene_cache: SkiaSceneCache,
+    options: SkiaRendererOptions,
+    base_color: Color,
+    alpha_type: ImageAlphaType,
+    anti_alias: bool,
+}
+
+impl SkiaImageRenderer {
+    /// Create a renderer with the specified scene cache options. Skia's global cache limits are
+    /// left unchanged (see [`SkiaGlobalCacheLimits`]).
+    pub fn with_options(width: u32, height: u32, options: SkiaRendererOptions) -> Self {
+        Self {
+            image_info: image_info(width, height),
+            surface_props: SurfaceProps::default(),
+            scene_cache: SkiaSceneCache::new(&options),
+            options,
+            base_color: Color::TRANSPARENT,
+            alpha_type: ImageAlphaType::Alpha,
+            anti_alias: true,
+        }
+    }
+
+    /// Change the eviction ages. Entries older than the new ages are evicted by the next render.
+    pub fn set_options(&mut self, options: SkiaRendererOptions) {
+        self.scene_cache.set_max_ages(&options);
+        self.options = options;
+    }
+
+    pub fn options(&self) -> &SkiaRendererOptions {
+        &self.options
+    }
+
+    /// Statistics about the renderer's caches (and Skia's global caches)
+    pub fn cache_stats(&self) -> SkiaCacheStats {
+        let mut stats = SkiaCacheStats::global();
+        self.scene_cache.record_stats(&mut stats);
+        stats
+    }
+
+    /// Set whether the rendered pixels have premultiplied ([`ImageAlphaType::AlphaPremultiplied`]) or
+    /// straight ([`ImageAlphaType::Alpha`], the default) alpha. Takes effect from the next render.
+    pub fn set_alpha_type(&mut self, alpha_type: ImageAlphaType) {
+        self.alpha_type = alpha_type;
+    }
+
+    /// Enable or disable antialiasing of shapes and text. Takes effect from the next render.
+    pub fn set_anti_alias(&mut self, anti_alias: bool) {
+        self.anti_alias = anti_alias;
+    }
 }
 
 impl ImageRenderer for SkiaImageRenderer {
@@ -17,29 +69,12 @@ impl ImageRenderer for SkiaImageRenderer {
         Self: 'a;
 
     fn new(width: u32, height: u32) -> Self {
-        graphics::set_font_cache_count_limit(100);
-        graphics::set_typeface_cache_count_limit(100);
-        graphics::set_resource_cache_total_bytes_limit(10485760);
-
-        Self {
-            image_info: ImageInfo::new(
-                (width as i32, height as i32),
-                ColorType::RGBA8888,
-                AlphaType::Opaque,
-                None,
-            ),
-            surface_props: SurfaceProps::default(),
-            scene_cache: SkiaSceneCache::default(),
-        }
+        SkiaGlobalCacheLimits::default().apply();
+        Self::with_options(width, height, SkiaRendererOptions::default())
     }
 
     fn resize(&mut self, width: u32, height: u32) {
-        self.image_info = ImageInfo::new(
-            (width as i32, height as i32),
-            ColorType::RGBA8888,
-            AlphaType::Opaque,
-            None,
-        );
+        self.image_info = image_info(width, height);
     }
 
     fn reset(&mut self) {}
@@ -49,6 +84,11 @@ impl ImageRenderer for SkiaImageRenderer {
         draw_fn: F,
         buffer: &mut Vec<u8>,
     ) {
+        buffer.resize(self.image_info.compute_min_byte_size(), 0);
+        self.render(draw_fn, &mut buffer[..]);
+    }
+
+    fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F, buffer: &mut [u8]) {
         debug_timer!(timer, feature = "log_frame_times");
 
         let mut surface = surfaces::wrap_pixels(
@@ -59,42 +99,78 @@ impl ImageRenderer for SkiaImageRenderer {
         )
         .unwrap();
 
-        surface.canvas().clear(Color::WHITE);
-
-        draw_fn(&mut SkiaScenePainter {
+        let mut painter = SkiaScenePainter {
             inner: surface.canvas(),
             cache: &mut self.scene_cache,
-        });
+            base_color: self.base_color,
+            anti_alias: self.anti_alias,
+        };
+        painter.reset();
+        draw_fn(&mut painter);
+        drop(surface);
         timer.record_time("render");
 
+        // Skia can only render to premultiplied surfaces
+        if self.alpha_type == ImageAlphaType::Alpha {
+            unpremultiply(buffer);
+            timer.record_time("unpremultiply");
+        }
+
         self.scene_cache.next_gen();
         timer.record_time("cache next gen");
 
         timer.print_times("skia_raster: ");
     }
 
-    fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F, buffer: &mut [u8]) {
-        debug_timer!(timer, feature = "log_frame_times");
-
-        let mut surface = surfaces::wrap_pixels(
-            &self.image_info,
-            &mut buffer[..],
-            None,
-            Some(&self.surface_props),
-        )
-        .unwrap();
-
-        surface.canvas().clear(Color::WHITE);
+    fn set_base_color(&mut self, color: Color) {
+        self.base_color = color;
+    }
+}
 
-        draw_fn(&mut SkiaScenePainter {
-            inner: surface.canvas(),
-            cache: &mut self.scene_cache,
-        });
-        timer.record_time("render");
+fn image_info(width: u32, height: u32) -> ImageInfo {
+    ImageInfo::new(
+        (width as i32, height as i32),
+        ColorType::RGBA8888,
+        AlphaType::Premul,
+        None,
+    )
+}
 
-        self.scene_cache.next_gen();
-        timer.record_time("cache next gen");
+/// Convert premultiplied RGBA8 pixels to straight alpha in place
+fn unpremultiply(buffer: &mut [u8]) {
+    for pixel in buffer.chunks_exact_mut(4) {
+        let alpha = pixel[3] as u32;
+        if alpha != 0 && alpha != 255 {
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+            }
+        }
+    }
+}
 
-        timer.print_times("skia_raster: ");
+#[cfg(test)]
+mod tests {
+    use super::unpremultiply;
+
+    #[test]
+    fn unpremultiply_pixels() {
+        let mut buffer = [
+            0, 0, 0, 0, // transparent
+            10, 20, 30, 255, // opaque
+            64, 32, 0, 128, // half transparent
+            1, 2, 3, 3, // rounds to nearest
+            200, 100, 0, 100, // invalid (color exceeds alpha), clamped
+        ];
+        unpremultiply(&mut buffer);
+        assert_eq!(
+            buffer,
+            [
+                0, 0, 0, 0, //
+                10, 20, 30, 255, //
+                128, 64, 0, 128, //
+                85, 170, 255, 3, //
+                255, 255, 0, 100, //
+            ]
+        );
     }
 }
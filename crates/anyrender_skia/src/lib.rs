@@ -1,4 +1,6 @@
 mod image_renderer;
+mod options;
+mod pdf_renderer;
 mod scene;
 mod window_renderer;
 
@@ -12,5 +14,7 @@ mod opengl;
 mod vulkan;
 
 pub use image_renderer::SkiaImageRenderer;
+pub use options::{SkiaCacheStats, SkiaGlobalCacheLimits, SkiaRendererOptions};
+pub use pdf_renderer::{SkiaPdfDocument, SkiaPdfMetadata, SkiaPdfRenderer};
 pub use scene::SkiaScenePainter;
 pub use window_renderer::*;
@@ -70,6 +70,10 @@ impl MetalBackend {
 }
 
 impl SkiaBackend for MetalBackend {
+    fn direct_context(&mut self) -> &mut DirectContext {
+        &mut self.skia
+    }
+
     fn set_size(&mut self, width: u32, height: u32) {
         self.metal_layer
             .setDrawableSize(CGSize::new(width as f64, height as f64));
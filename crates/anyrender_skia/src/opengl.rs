@@ -169,6 +169,10 @@ impl OpenGLBackend {
 }
 
 impl SkiaBackend for OpenGLBackend {
+    fn direct_context(&mut self) -> &mut DirectContext {
+        &mut self.gr_context
+    }
+
     fn set_size(&mut self, width: u32, height: u32) {
         self.surface = Some(Self::create_surface(
             width,
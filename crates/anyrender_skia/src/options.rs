@@ -0,0 +1,109 @@
+use skia_safe::graphics;
+
+/// Limits for Skia's font, typeface and resource caches. These caches are global to the process, so
+/// the limits are shared by all renderers and stay in effect until [`SkiaGlobalCacheLimits::apply`] is
+/// next called.
+///
+/// The renderers' `new` constructors apply the default limits. Renderers created with `with_options`
+/// leave the limits unchanged.
+#[derive(Clone, Copy, Debug)]
+pub struct SkiaGlobalCacheLimits {
+    /// The maximum number of fonts in the glyph cache
+    pub font_cache_count_limit: i32,
+    /// The maximum total size (in bytes) of the glyph cache
+    pub font_cache_bytes_limit: usize,
+    /// The maximum number of typefaces in the typeface cache
+    pub typeface_cache_count_limit: i32,
+    /// The maximum total size (in bytes) of the resource cache
+    pub resource_cache_bytes_limit: usize,
+}
+
+impl Default for SkiaGlobalCacheLimits {
+    fn default() -> Self {
+        Self {
+            font_cache_count_limit: 100,
+            font_cache_bytes_limit: 2 * 1024 * 1024,
+            typeface_cache_count_limit: 100,
+            resource_cache_bytes_limit: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl SkiaGlobalCacheLimits {
+    /// Apply the limits to Skia's global caches (for all renderers in the process)
+    pub fn apply(&self) {
+        graphics::set_font_cache_count_limit(self.font_cache_count_limit);
+        graphics::set_font_cache_limit(self.font_cache_bytes_limit);
+        graphics::set_typeface_cache_count_limit(self.typeface_cache_count_limit);
+        graphics::set_resource_cache_total_bytes_limit(self.resource_cache_bytes_limit);
+    }
+}
+
+/// Options controlling the caches owned by a Skia renderer. Skia's global caches are configured
+/// separately (see [`SkiaGlobalCacheLimits`]).
+#[derive(Clone, Debug)]
+pub struct SkiaRendererOptions {
+    /// The maximum total size (in bytes) of the GPU resources cached by the window renderer's GPU context.
+    /// `None` uses Skia's default.
+    pub gpu_resource_cache_bytes_limit: Option<usize>,
+    /// The number of frames that a typeface can go unused before it is evicted from the scene cache
+    pub typeface_max_age: usize,
+    /// The number of frames that a font (typeface at a given size) can go unused before it is evicted from the scene cache
+    pub font_max_age: usize,
+    /// The number of frames that an image can go unused before it is evicted from the scene cache
+    pub image_max_age: usize,
+    /// The number of frames that a glyph run can go unused before it is evicted from the scene cache.
+    /// Text blobs are small (a glyph id and position per glyph), so by default they are kept long
+    /// enough for text that is briefly hidden (e.g. when scrolled out of view) to stay cached.
+    pub text_blob_max_age: usize,
+    /// Whether to purge Skia's global caches when the window renderer is suspended
+    pub purge_on_suspend: bool,
+}
+
+impl Default for SkiaRendererOptions {
+    fn default() -> Self {
+        Self {
+            gpu_resource_cache_bytes_limit: None,
+            typeface_max_age: 1,
+            font_max_age: 10,
+            image_max_age: 1,
+            text_blob_max_age: 10,
+            purge_on_suspend: false,
+        }
+    }
+}
+
+/// Statistics about the caches used by a Skia renderer
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SkiaCacheStats {
+    /// The number of fonts in Skia's (global) glyph cache
+    pub font_cache_count: i32,
+    /// The total size (in bytes) of Skia's (global) glyph cache
+    pub font_cache_bytes: usize,
+    /// The total size (in bytes) of Skia's (global) resource cache
+    pub resource_cache_bytes: usize,
+    /// The number of resources cached by the window renderer's GPU context
+    pub gpu_resource_count: usize,
+    /// The total size (in bytes) of the resources cached by the window renderer's GPU context
+    pub gpu_resource_bytes: usize,
+    /// The number of typefaces in the scene cache
+    pub typefaces: usize,
+    /// The number of fonts in the scene cache
+    pub fonts: usize,
+    /// The number of images in the scene cache
+    pub images: usize,
+    /// The number of text blobs (glyph runs) in the scene cache
+    pub text_blobs: usize,
+}
+
+impl SkiaCacheStats {
+    /// Statistics for Skia's global caches (the remaining fields are zero)
+    pub(crate) fn global() -> Self {
+        Self {
+            font_cache_count: graphics::font_cache_count_used(),
+            font_cache_bytes: graphics::font_cache_used(),
+            resource_cache_bytes: graphics::resource_cache_total_bytes_used(),
+            ..Default::default()
+        }
+    }
+}
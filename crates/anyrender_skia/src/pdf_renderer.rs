@@ -0,0 +1,191 @@
+use std::io::{self, Write};
+
+use anyrender::PaintScene;
+use debug_timer::debug_timer;
+use kurbo::Size;
+use peniko::Color;
+use skia_safe::{Document, pdf};
+
+use crate::{SkiaGlobalCacheLimits, SkiaRendererOptions, SkiaScenePainter, scene::SkiaSceneCache};
+
+/// Document information stored in the PDF
+#[derive(Clone, Debug, Default)]
+pub struct SkiaPdfMetadata {
+    pub title: String,
+    pub author: String,
+    pub subject: String,
+    /// Comma-separated keywords
+    pub keywords: String,
+    /// The application that created the original content
+    pub creator: String,
+    /// The application that converted the content to PDF (defaults to Skia)
+    pub producer: Option<String>,
+    /// The natural language of the text (e.g. "en-US")
+    pub language: String,
+}
+
+/// Renders scenes to (multi-page) PDF documents.
+///
+/// Shapes and text are written as vectors. The fonts used by text are embedded (subsetted) in the
+/// document, so text stays selectable and searchable.
+///
+/// As the output isn't pixels, this doesn't implement [`ImageRenderer`](anyrender::ImageRenderer).
+/// Each page is drawn with a [`SkiaScenePainter`], so any code which draws to a [`PaintScene`] can
+/// draw pages.
+pub struct SkiaPdfRenderer {
+    scene_cache: SkiaSceneCache,
+    metadata: SkiaPdfMetadata,
+    base_color: Color,
+}
+
+impl Default for SkiaPdfRenderer {
+    fn default() -> Self {
+        Self::new(SkiaPdfMetadata::default())
+    }
+}
+
+impl SkiaPdfRenderer {
+    /// Create a renderer with the default options, and apply the default [`SkiaGlobalCacheLimits`]
+    pub fn new(metadata: SkiaPdfMetadata) -> Self {
+        SkiaGlobalCacheLimits::default().apply();
+        Self::with_options(metadata, SkiaRendererOptions::default())
+    }
+
+    /// Create a renderer with the specified scene cache options. Skia's global cache limits are
+    /// left unchanged (see [`SkiaGlobalCacheLimits`]).
+    pub fn with_options(metadata: SkiaPdfMetadata, options: SkiaRendererOptions) -> Self {
+        Self {
+            scene_cache: SkiaSceneCache::new(&options),
+            metadata,
+            base_color: Color::TRANSPARENT,
+        }
+    }
+
+    pub fn set_metadata(&mut self, metadata: SkiaPdfMetadata) {
+        self.metadata = metadata;
+    }
+
+    /// Set the color that each page is filled with before drawing it. Pages are left
+    /// unfilled if the color is transparent (the default).
+    pub fn set_base_color(&mut self, color: Color) {
+        self.base_color = color;
+    }
+
+    /// Render a document to `writer`. Pages are added to the document by `draw_fn`.
+    ///
+    /// Returns the first error from `writer`, after which nothing more is written to it (though
+    /// `draw_fn` still runs).
+    pub fn render<W: Write, F: FnOnce(&mut SkiaPdfDocument<'_, '_>)>(
+        &mut self,
+        writer: &mut W,
+        draw_fn: F,
+    ) -> io::Result<()> {
+        debug_timer!(timer, feature = "log_frame_times");
+
+        let metadata = pdf::Metadata {
+            title: self.metadata.title.clone(),
+            author: self.metadata.author.clone(),
+            subject: self.metadata.subject.clone(),
+            keywords: self.metadata.keywords.clone(),
+            creator: self.metadata.creator.clone(),
+            producer: self
+                .metadata
+                .producer
+                .clone()
+                .unwrap_or_else(|| pdf::Metadata::default().producer),
+            lang: self.metadata.language.clone(),
+            ..Default::default()
+        };
+
+        let mut writer = ErrorRecordingWriter {
+            inner: writer,
+            error: None,
+        };
+        let mut document = SkiaPdfDocument {
+            document: Some(pdf::new_document(&mut writer, Some(&metadata))),
+            scene_cache: &mut self.scene_cache,
+            base_color: self.base_color,
+        };
+        draw_fn(&mut document);
+        timer.record_time("render");
+
+        if let Some(document) = document.document.take() {
+            document.close();
+        }
+        drop(document);
+        timer.record_time("close");
+
+        timer.print_times("skia_pdf: ");
+
+        match writer.error {
+            Some(error) => Err(error),
+            None => writer.inner.flush(),
+        }
+    }
+
+    /// Render a document into a new `Vec`. Pages are added to the document by `draw_fn`.
+    pub fn render_to_vec<F: FnOnce(&mut SkiaPdfDocument<'_, '_>)>(
+        &mut self,
+        draw_fn: F,
+    ) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        self.render(&mut buffer, draw_fn)
+            .expect("Writing to a Vec can't fail");
+        buffer
+    }
+}
+
+/// Keeps the first error from the inner writer, as Skia discards it
+struct ErrorRecordingWriter<'w, W> {
+    inner: &'w mut W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Write for ErrorRecordingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Err(io::ErrorKind::Other.into());
+        }
+        match self.inner.write_all(buf) {
+            Ok(()) => Ok(buf.len()),
+            Err(error) => {
+                let kind = error.kind();
+                self.error = Some(error);
+                Err(kind.into())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A PDF document that is being rendered by a [`SkiaPdfRenderer`]
+pub struct SkiaPdfDocument<'a, 'w> {
+    document: Option<Document<'w>>,
+    scene_cache: &'a mut SkiaSceneCache,
+    base_color: Color,
+}
+
+impl SkiaPdfDocument<'_, '_> {
+    /// Add a page of `size` (in points, 1/72 inch) to the end of the document, drawn by `draw_fn`
+    pub fn add_page<F: FnOnce(&mut SkiaScenePainter<'_>)>(&mut self, size: Size, draw_fn: F) {
+        let document = self.document.take().unwrap();
+        let mut page = document.begin_page((size.width as f32, size.height as f32), None);
+
+        let mut painter = SkiaScenePainter {
+            inner: page.canvas(),
+            cache: &mut *self.scene_cache,
+            base_color: self.base_color,
+            anti_alias: true,
+        };
+        if self.base_color.components[3] > 0.0 {
+            painter.reset();
+        }
+        draw_fn(&mut painter);
+
+        self.document = Some(page.end_page());
+        self.scene_cache.next_gen();
+    }
+}
@@ -1,16 +1,18 @@
 use anyrender::PaintScene;
 use skia_safe::{
-    BlurStyle, Canvas, Color, ColorSpace, Font, FontArguments, FontHinting, FontMgr, GlyphId,
-    MaskFilter, Paint, PaintCap, PaintJoin, PaintStyle, Point, RRect, Rect, Shader, Typeface,
-    canvas::{GlyphPositions, SaveLayerRec},
+    BlurStyle, Canvas, ColorSpace, Font, FontArguments, FontHinting, FontMgr, GlyphId, Image,
+    MaskFilter, Paint, PaintCap, PaintJoin, PaintStyle, PathEffect, Point, RRect, Rect, TextBlob,
+    TextBlobBuilder, Typeface,
+    canvas::SaveLayerRec,
     font::Edging,
     font_arguments::{VariationPosition, variation_position::Coordinate},
 };
 
 use crate::cache::{
     FontCacheKey, FontCacheKeyBorrowed, GenerationalCache, NormalizedTypefaceCacheKey,
-    NormalizedTypefaceCacheKeyBorrowed,
+    NormalizedTypefaceCacheKeyBorrowed, TextBlobCacheKey, TextBlobCacheKeyBorrowed,
 };
+use crate::{SkiaCacheStats, SkiaRendererOptions};
 
 pub(crate) struct SkiaSceneCache {
     paint: Paint,
@@ -18,51 +20,76 @@ pub(crate) struct SkiaSceneCache {
     extracted_font_data: GenerationalCache<(u64, u32), peniko::FontData>,
     typeface: GenerationalCache<(u64, u32), Typeface>,
     normalized_typeface: GenerationalCache<NormalizedTypefaceCacheKey, Typeface>,
-    image_shader: GenerationalCache<u64, Shader>,
+    image: GenerationalCache<u64, Image>,
     font: GenerationalCache<FontCacheKey, Font>,
+    text_blob: GenerationalCache<TextBlobCacheKey, TextBlob>,
     font_mgr: FontMgr,
     glyph_id_buf: Vec<GlyphId>,
     glyph_pos_buf: Vec<Point>,
 }
 
 impl SkiaSceneCache {
-    pub(crate) fn next_gen(&mut self) {
-        self.typeface.next_gen();
-        self.normalized_typeface.next_gen();
-        self.image_shader.next_gen();
-        self.font.next_gen();
-    }
-}
-
-impl Default for SkiaSceneCache {
-    fn default() -> Self {
+    pub(crate) fn new(options: &SkiaRendererOptions) -> Self {
         Self {
             paint: Paint::default(),
             #[cfg(target_os = "macos")]
-            extracted_font_data: GenerationalCache::new(10),
-            typeface: GenerationalCache::new(1),
-            normalized_typeface: GenerationalCache::new(1),
-            image_shader: GenerationalCache::new(1),
-            font: GenerationalCache::new(10),
+            extracted_font_data: GenerationalCache::new(options.font_max_age),
+            typeface: GenerationalCache::new(options.typeface_max_age),
+            normalized_typeface: GenerationalCache::new(options.typeface_max_age),
+            image: GenerationalCache::new(options.image_max_age),
+            font: GenerationalCache::new(options.font_max_age),
+            text_blob: GenerationalCache::new(options.text_blob_max_age),
             font_mgr: FontMgr::new(),
             glyph_id_buf: Default::default(),
             glyph_pos_buf: Default::default(),
         }
     }
+
+    pub(crate) fn set_max_ages(&mut self, options: &SkiaRendererOptions) {
+        #[cfg(target_os = "macos")]
+        self.extracted_font_data.set_max_age(options.font_max_age);
+        self.typeface.set_max_age(options.typeface_max_age);
+        self.normalized_typeface
+            .set_max_age(options.typeface_max_age);
+        self.image.set_max_age(options.image_max_age);
+        self.font.set_max_age(options.font_max_age);
+        self.text_blob.set_max_age(options.text_blob_max_age);
+    }
+
+    /// Fill in the scene cache fields of `stats`
+    pub(crate) fn record_stats(&self, stats: &mut SkiaCacheStats) {
+        stats.typefaces = self.typeface.len() + self.normalized_typeface.len();
+        stats.fonts = self.font.len();
+        stats.images = self.image.len();
+        stats.text_blobs = self.text_blob.len();
+    }
+
+    pub(crate) fn next_gen(&mut self) {
+        self.typeface.next_gen();
+        self.normalized_typeface.next_gen();
+        self.image.next_gen();
+        self.font.next_gen();
+        self.text_blob.next_gen();
+    }
 }
 
 pub struct SkiaScenePainter<'a> {
     pub(crate) inner: &'a Canvas,
     pub(crate) cache: &'a mut SkiaSceneCache,
+    /// The color that the canvas is cleared to when the scene is reset
+    pub(crate) base_color: peniko::Color,
+    pub(crate) anti_alias: bool,
 }
 
 impl SkiaScenePainter<'_> {
     fn reset_paint(&mut self) {
         self.cache.paint.reset();
-        self.cache.paint.set_anti_alias(true);
+        self.cache.paint.set_anti_alias(self.anti_alias);
     }
 
+    /// Multiply the paint's alpha (including that of a solid color brush) by `alpha`
     fn set_paint_alpha(&mut self, alpha: f32) {
+        let alpha = self.cache.paint.alpha_f() * alpha;
         self.cache.paint.set_alpha_f(alpha);
     }
 
@@ -111,20 +138,24 @@ impl SkiaScenePainter<'_> {
                     .set_shader(sk_peniko::shader_from_gradient(gradient, brush_transform));
             }
             anyrender::Paint::Image(image_brush) => {
-                if let Some(shader) = self.cache.image_shader.hit(&image_brush.image.data.id()) {
-                    self.cache.paint.set_shader(shader.clone());
-                    return;
-                }
-
-                let image_shader = sk_peniko::shader_from_image_brush(image_brush, brush_transform);
-
-                if let Some(shader) = &image_shader {
-                    self.cache
-                        .image_shader
-                        .insert(image_brush.image.data.id(), shader.clone());
-                }
+                // Only the image is cached: the shader also depends on the brush transform and sampler,
+                // and is cheap to create from an existing image.
+                let image_id = image_brush.image.data.id();
+                let image = match self.cache.image.hit(&image_id) {
+                    Some(image) => image.clone(),
+                    None => {
+                        let image = sk_peniko::image_from_image_data(image_brush.image);
+                        self.cache.image.insert(image_id, image.clone());
+                        image
+                    }
+                };
 
-                self.cache.paint.set_shader(image_shader);
+                self.cache.paint.set_shader(sk_peniko::shader_from_image(
+                    image,
+                    &image_brush.sampler,
+                    brush_transform,
+                ));
+                self.set_paint_alpha(image_brush.sampler.alpha);
             }
             anyrender::Paint::Custom(_) => unreachable!(), // ToDo: figure out what to do with this
         }
@@ -144,11 +175,25 @@ impl SkiaScenePainter<'_> {
                     kurbo::Join::Miter => PaintJoin::Miter,
                     kurbo::Join::Round => PaintJoin::Round,
                 });
+                self.cache.paint.set_stroke_miter(stroke.miter_limit as f32);
+                // Skia only supports a single cap. Strokes with differing caps are expanded into fills
+                // (see `PaintScene::stroke`), so this is only reached when the caps are the same.
                 self.cache.paint.set_stroke_cap(match stroke.start_cap {
                     kurbo::Cap::Butt => PaintCap::Butt,
                     kurbo::Cap::Square => PaintCap::Square,
                     kurbo::Cap::Round => PaintCap::Round,
                 });
+                if !stroke.dash_pattern.is_empty() {
+                    // Skia requires an even number of intervals. Like SVG, odd patterns are repeated.
+                    let mut intervals: Vec<f32> =
+                        stroke.dash_pattern.iter().map(|&d| d as f32).collect();
+                    if intervals.len() % 2 == 1 {
+                        intervals.extend_from_within(..);
+                    }
+                    self.cache
+                        .paint
+                        .set_path_effect(PathEffect::dash(&intervals, stroke.dash_offset as f32));
+                }
             }
         }
     }
@@ -364,7 +409,8 @@ impl SkiaScenePainter<'_> {
 
 impl PaintScene for SkiaScenePainter<'_> {
     fn reset(&mut self) {
-        self.inner.clear(Color::WHITE);
+        self.inner
+            .clear(sk_peniko::color4f_from_alpha_color(self.base_color));
     }
 
     fn push_layer(
@@ -419,6 +465,22 @@ impl PaintScene for SkiaScenePainter<'_> {
 
         self.reset_paint();
         self.set_paint_brush(brush, brush_transform);
+
+        if style.start_cap != style.end_cap {
+            // Skia can't draw strokes with different start and end caps, so expand the stroke
+            // (including any dashes) into its outline using kurbo and fill that instead
+            let tolerance = 0.1 / transform.determinant().abs().sqrt().max(1e-6);
+            let outline = kurbo::stroke(
+                shape.path_elements(tolerance),
+                style,
+                &kurbo::StrokeOpts::default(),
+                tolerance,
+            );
+            self.set_paint_style(peniko::Fill::NonZero);
+            self.draw_shape_with_fill(&outline, peniko::Fill::NonZero);
+            return;
+        }
+
         self.set_paint_style(style);
         self.draw_shape(shape);
     }
@@ -463,7 +525,7 @@ impl PaintScene for SkiaScenePainter<'_> {
         self.set_paint_style(style);
         self.set_paint_alpha(brush_alpha);
 
-        let Some(font) = self.get_or_cache_font(font, normalized_coords, font_size, hint) else {
+        let Some(sk_font) = self.get_or_cache_font(font, normalized_coords, font_size, hint) else {
             return;
         };
 
@@ -471,18 +533,53 @@ impl PaintScene for SkiaScenePainter<'_> {
         self.cache.glyph_id_buf.reserve(min_size);
         self.cache.glyph_pos_buf.reserve(min_size);
 
+        // Positions are stored relative to the first glyph so that a run which has moved can reuse
+        // the cached blob
+        let mut origin = None;
         for glyph in glyphs {
+            let position = Point::new(glyph.x, glyph.y);
+            let origin = *origin.get_or_insert(position);
             self.cache.glyph_id_buf.push(GlyphId::from(glyph.id as u16));
-            self.cache.glyph_pos_buf.push(Point::new(glyph.x, glyph.y));
+            self.cache.glyph_pos_buf.push(position - origin);
         }
+        let origin = origin.unwrap_or_default();
 
-        self.inner.draw_glyphs_at(
-            &self.cache.glyph_id_buf[..],
-            GlyphPositions::Points(&self.cache.glyph_pos_buf[..]),
-            Point::new(0.0, 0.0),
-            &font,
-            &self.cache.paint,
-        );
+        let cache_key_borrowed = TextBlobCacheKeyBorrowed {
+            font: FontCacheKeyBorrowed {
+                typeface_id: font.data.id(),
+                typeface_index: font.index,
+                normalized_coords,
+                font_size: font_size.to_bits(),
+                hint,
+            },
+            glyph_ids: &self.cache.glyph_id_buf[..],
+            positions: &self.cache.glyph_pos_buf[..],
+        };
+
+        let text_blob = match self.cache.text_blob.hit(&cache_key_borrowed) {
+            Some(text_blob) => Some(text_blob.clone()),
+            None => {
+                let mut builder = TextBlobBuilder::new();
+                let (glyph_ids, positions) =
+                    builder.alloc_run_pos(&sk_font, self.cache.glyph_id_buf.len(), None);
+                glyph_ids.copy_from_slice(&self.cache.glyph_id_buf);
+                positions.copy_from_slice(&self.cache.glyph_pos_buf);
+
+                // Empty glyph runs don't produce a blob
+                let text_blob = builder.make();
+                if let Some(text_blob) = &text_blob {
+                    self.cache
+                        .text_blob
+                        .insert(cache_key_borrowed.to_owned_key(), text_blob.clone());
+                }
+                text_blob
+            }
+        };
+
+        if let Some(text_blob) = text_blob {
+            self.inner
+                .draw_text_blob(&text_blob, origin, &self.cache.paint);
+        }
 
         self.cache.glyph_id_buf.clear();
         self.cache.glyph_pos_buf.clear();
@@ -532,8 +629,8 @@ fn lerp_f32(a: f32, b: f32, t: f32) -> f32 {
 mod sk_peniko {
     use peniko::color::{AlphaColor, ColorSpaceTag, HueDirection, Srgb};
     use peniko::{
-        BlendMode, Compose, Extend, Gradient, GradientKind, ImageAlphaType, ImageBrush, ImageData,
-        ImageFormat, Mix,
+        BlendMode, Compose, Extend, Gradient, GradientKind, ImageAlphaType, ImageData, ImageFormat,
+        ImageSampler, Mix,
     };
     use peniko::{Fill, color::DynamicColor};
     use skia_safe::AlphaType as SkAlphaType;
@@ -541,6 +638,7 @@ mod sk_peniko {
     use skia_safe::Color4f as SkColor4f;
     use skia_safe::ColorType as SkColorType;
     use skia_safe::Data as SkData;
+    use skia_safe::Image as SkImage;
     use skia_safe::ImageInfo as SkImageInfo;
     use skia_safe::PathFillType as SkPathFillType;
     use skia_safe::SamplingOptions as SkSamplingOptions;
@@ -549,12 +647,7 @@ mod sk_peniko {
     use skia_safe::gradient_shader::interpolation::ColorSpace as SkGradientShaderColorSpace;
     use skia_safe::gradient_shader::interpolation::HueMethod as SkGradientShaderHueMethod;
 
-    pub(super) fn shader_from_image_brush(
-        image_brush: ImageBrush<&ImageData>,
-        brush_transform: Option<kurbo::Affine>,
-    ) -> Option<SkShader> {
-        let image_data = image_brush.image;
-
+    pub(super) fn image_from_image_data(image_data: &ImageData) -> SkImage {
         let image_info = SkImageInfo::new(
             (image_data.width as i32, image_data.height as i32),
             match image_data.format {
@@ -568,14 +661,18 @@ mod sk_peniko {
             },
             None,
         );
-        let pixels = unsafe {
-            SkData::new_bytes(image_data.data.data()) // We have to ensure the src image data lives long enough
-        };
-        let image =
-            skia_safe::images::raster_from_data(&image_info, pixels, image_info.min_row_bytes())
-                .unwrap();
+        // The image is cached across frames, so it can't borrow the src image data
+        let pixels = SkData::new_copy(image_data.data.data());
+        skia_safe::images::raster_from_data(&image_info, pixels, image_info.min_row_bytes())
+            .unwrap()
+    }
 
-        let sampling = match image_brush.sampler.quality {
+    pub(super) fn shader_from_image(
+        image: SkImage,
+        sampler: &ImageSampler,
+        brush_transform: Option<kurbo::Affine>,
+    ) -> Option<SkShader> {
+        let sampling = match sampler.quality {
             peniko::ImageQuality::Low => {
                 SkSamplingOptions::new(skia_safe::FilterMode::Nearest, skia_safe::MipmapMode::None)
             }
@@ -591,8 +688,8 @@ mod sk_peniko {
         skia_safe::shaders::image(
             image,
             (
-                tile_mode_from_extend(image_brush.sampler.x_extend),
-                tile_mode_from_extend(image_brush.sampler.y_extend),
+                tile_mode_from_extend(sampler.x_extend),
+                tile_mode_from_extend(sampler.y_extend),
             ),
             &sampling,
             &brush_transform.map(super::sk_kurbo::matrix_from_affine),
@@ -222,6 +222,10 @@ impl Drop for VulkanBackend {
 }
 
 impl SkiaBackend for VulkanBackend {
+    fn direct_context(&mut self) -> &mut DirectContext {
+        &mut self.gr_context
+    }
+
     fn set_size(&mut self, width: u32, height: u32) {
         self.swapchain_size = (width, height);
         self.recreate_swapchain();
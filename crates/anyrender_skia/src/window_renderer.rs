@@ -1,9 +1,13 @@
-use anyrender::WindowRenderer;
+use anyrender::{PaintScene, WindowRenderer};
 use debug_timer::debug_timer;
-use skia_safe::{Color, Surface, graphics};
+use peniko::Color;
+use skia_safe::{Surface, gpu::DirectContext, graphics};
 use std::sync::Arc;
 
-use crate::{SkiaScenePainter, scene::SkiaSceneCache};
+use crate::{
+    SkiaCacheStats, SkiaGlobalCacheLimits, SkiaRendererOptions, SkiaScenePainter,
+    scene::SkiaSceneCache,
+};
 
 pub(crate) trait SkiaBackend {
     fn set_size(&mut self, width: u32, height: u32);
@@ -11,6 +15,8 @@ pub(crate) trait SkiaBackend {
     fn prepare(&mut self) -> Option<Surface>;
 
     fn flush(&mut self, surface: Surface);
+
+    fn direct_context(&mut self) -> &mut DirectContext;
 }
 
 enum RenderState {
@@ -21,10 +27,25 @@ enum RenderState {
 struct ActiveRenderState {
     backend: Box<dyn SkiaBackend>,
     scene_cache: SkiaSceneCache,
+    // The GPU resource cache usage at the end of the last frame. Recorded after each frame as reading
+    // it requires mutable access to the backend's `DirectContext`.
+    gpu_resource_count: usize,
+    gpu_resource_bytes: usize,
+}
+
+impl ActiveRenderState {
+    fn record_gpu_usage(&mut self) {
+        let usage = self.backend.direct_context().resource_cache_usage();
+        self.gpu_resource_count = usage.resource_count;
+        self.gpu_resource_bytes = usage.resource_bytes;
+    }
 }
 
 pub struct SkiaWindowRenderer {
     render_state: RenderState,
+    options: SkiaRendererOptions,
+    base_color: Color,
+    anti_alias: bool,
 }
 
 impl Default for SkiaWindowRenderer {
@@ -34,14 +55,60 @@ impl Default for SkiaWindowRenderer {
 }
 
 impl SkiaWindowRenderer {
+    /// Create a renderer with the default options, and apply the default [`SkiaGlobalCacheLimits`]
     pub fn new() -> Self {
+        SkiaGlobalCacheLimits::default().apply();
+        Self::with_options(SkiaRendererOptions::default())
+    }
+
+    /// Create a renderer with the specified scene and GPU cache options. Skia's global cache limits
+    /// are left unchanged (see [`SkiaGlobalCacheLimits`]).
+    pub fn with_options(options: SkiaRendererOptions) -> Self {
         Self {
             render_state: RenderState::Suspended,
+            options,
+            base_color: Color::WHITE,
+            anti_alias: true,
+        }
+    }
+
+    /// Enable or disable antialiasing of shapes and text. Takes effect from the next frame.
+    pub fn set_anti_alias(&mut self, anti_alias: bool) {
+        self.anti_alias = anti_alias;
+    }
+
+    /// Change the GPU cache limit and eviction ages. Caches exceeding the new limits are trimmed by the next frame.
+    pub fn set_options(&mut self, options: SkiaRendererOptions) {
+        if let RenderState::Active(state) = &mut self.render_state {
+            apply_gpu_limits(&options, state.backend.direct_context());
+            state.scene_cache.set_max_ages(&options);
         }
+        self.options = options;
+    }
+
+    pub fn options(&self) -> &SkiaRendererOptions {
+        &self.options
+    }
+
+    /// Statistics about the renderer's caches (and Skia's global caches). The GPU cache statistics are
+    /// those at the end of the last frame. The scene and GPU cache statistics are zero while the renderer
+    /// is suspended.
+    pub fn cache_stats(&self) -> SkiaCacheStats {
+        let mut stats = SkiaCacheStats::global();
+        if let RenderState::Active(state) = &self.render_state {
+            state.scene_cache.record_stats(&mut stats);
+            stats.gpu_resource_count = state.gpu_resource_count;
+            stats.gpu_resource_bytes = state.gpu_resource_bytes;
+        }
+        stats
     }
 }
 
-impl SkiaWindowRenderer {}
+fn apply_gpu_limits(options: &SkiaRendererOptions, direct_context: &mut DirectContext) {
+    if let Some(limit) = options.gpu_resource_cache_bytes_limit {
+        direct_context.set_resource_cache_limit(limit);
+    }
+}
 
 impl WindowRenderer for SkiaWindowRenderer {
     type ScenePainter<'a>
@@ -50,23 +117,25 @@ impl WindowRenderer for SkiaWindowRenderer {
         Self: 'a;
 
     fn resume(&mut self, window: Arc<dyn anyrender::WindowHandle>, width: u32, height: u32) {
-        graphics::set_font_cache_count_limit(100);
-        graphics::set_typeface_cache_count_limit(100);
-        graphics::set_resource_cache_total_bytes_limit(10485760);
-
         #[cfg(target_os = "macos")]
-        let backend = crate::metal::MetalBackend::new(window, width, height);
+        let mut backend = crate::metal::MetalBackend::new(window, width, height);
         #[cfg(not(target_os = "macos"))]
-        let backend = crate::opengl::OpenGLBackend::new(window, width, height);
+        let mut backend = crate::opengl::OpenGLBackend::new(window, width, height);
+        apply_gpu_limits(&self.options, backend.direct_context());
 
         self.render_state = RenderState::Active(Box::new(ActiveRenderState {
             backend: Box::new(backend),
-            scene_cache: SkiaSceneCache::default(),
+            scene_cache: SkiaSceneCache::new(&self.options),
+            gpu_resource_count: 0,
+            gpu_resource_bytes: 0,
         }))
     }
 
     fn suspend(&mut self) {
         self.render_state = RenderState::Suspended;
+        if self.options.purge_on_suspend {
+            graphics::purge_all_caches();
+        }
     }
 
     fn is_active(&self) -> bool {
@@ -92,22 +161,30 @@ impl WindowRenderer for SkiaWindowRenderer {
         };
 
         surface.canvas().restore_to_count(1);
-        surface.canvas().clear(Color::WHITE);
 
-        draw_fn(&mut SkiaScenePainter {
+        let mut painter = SkiaScenePainter {
             inner: surface.canvas(),
             cache: &mut state.scene_cache,
-        });
+            base_color: self.base_color,
+            anti_alias: self.anti_alias,
+        };
+        painter.reset();
+        draw_fn(&mut painter);
         timer.record_time("cmd");
 
         state.backend.flush(surface);
         timer.record_time("render");
 
         state.scene_cache.next_gen();
+        state.record_gpu_usage();
         timer.record_time("cache next gen");
 
         timer.print_times("skia: ");
     }
+
+    fn set_base_color(&mut self, color: Color) {
+        self.base_color = color;
+    }
 }
 
 #[cfg(any(
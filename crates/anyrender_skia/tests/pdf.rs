@@ -0,0 +1,66 @@
+//! Renders multi-page documents with the PDF renderer
+
+use std::io::{self, Write};
+
+use anyrender::PaintScene;
+use anyrender_skia::SkiaPdfRenderer;
+use kurbo::{Affine, Rect, Size};
+use peniko::{Color, Fill};
+
+const PAGE_SIZE: Size = Size::new(595.0, 842.0);
+
+fn draw_page(scene: &mut impl PaintScene, color: Color) {
+    let rect = Rect::new(72.0, 72.0, 288.0, 216.0);
+    scene.fill(Fill::NonZero, Affine::IDENTITY, color, None, &rect);
+}
+
+/// Counts the page objects in the document (excluding the `/Pages` tree)
+fn count_pages(pdf: &[u8]) -> usize {
+    let needle = b"/Type /Page";
+    pdf.windows(needle.len() + 1)
+        .filter(|window| window.starts_with(needle) && window[needle.len()] != b's')
+        .count()
+}
+
+#[test]
+fn two_pages() {
+    let mut renderer = SkiaPdfRenderer::default();
+    let pdf = renderer.render_to_vec(|document| {
+        document.add_page(PAGE_SIZE, |scene| draw_page(scene, Color::BLACK));
+        document.add_page(PAGE_SIZE, |scene| draw_page(scene, Color::WHITE));
+    });
+
+    assert!(pdf.starts_with(b"%PDF-"));
+    assert_eq!(count_pages(&pdf), 2);
+}
+
+/// A writer which fails after accepting `remaining` bytes
+struct FailingWriter {
+    remaining: usize,
+}
+
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Err(io::Error::other("disk full"));
+        }
+        let len = buf.len().min(self.remaining);
+        self.remaining -= len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn writer_error() {
+    let mut renderer = SkiaPdfRenderer::default();
+    let result = renderer.render(&mut FailingWriter { remaining: 16 }, |document| {
+        document.add_page(PAGE_SIZE, |scene| draw_page(scene, Color::BLACK));
+    });
+
+    let error = result.unwrap_err();
+    assert_eq!(error.to_string(), "disk full");
+}
@@ -0,0 +1,97 @@
+//! Compares strokes rendered by Skia against vello_cpu
+
+use anyrender::{ImageRenderer, PaintScene};
+use anyrender_skia::SkiaImageRenderer;
+use anyrender_vello_cpu::VelloCpuImageRenderer;
+use kurbo::{Affine, BezPath, Cap, Join, Stroke};
+use peniko::Color;
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 64;
+
+fn render<R: ImageRenderer>(stroke: &Stroke, path: &BezPath) -> Vec<u8> {
+    let mut renderer = R::new(WIDTH, HEIGHT);
+    renderer.set_base_color(Color::WHITE);
+    let mut buffer = vec![0; (WIDTH * HEIGHT * 4) as usize];
+    renderer.render(
+        |scene| scene.stroke(stroke, Affine::IDENTITY, Color::BLACK, None, path),
+        &mut buffer,
+    );
+    buffer
+}
+
+/// Asserts that Skia and vello_cpu render the stroke (almost) identically. Pixels along edges
+/// are allowed to differ due to differences in antialiasing.
+fn assert_matches_vello_cpu(stroke: Stroke, path: BezPath) {
+    let skia = render::<SkiaImageRenderer>(&stroke, &path);
+    let vello_cpu = render::<VelloCpuImageRenderer>(&stroke, &path);
+
+    let differing_pixels = skia
+        .chunks_exact(4)
+        .zip(vello_cpu.chunks_exact(4))
+        .filter(|(a, b)| a.iter().zip(b.iter()).any(|(a, b)| a.abs_diff(*b) > 96))
+        .count();
+    let max_differing_pixels = (WIDTH * HEIGHT) as usize / 100;
+    assert!(
+        differing_pixels <= max_differing_pixels,
+        "{differing_pixels} pixels differ (maximum {max_differing_pixels})"
+    );
+}
+
+fn horizontal_line() -> BezPath {
+    let mut path = BezPath::new();
+    path.move_to((16.0, 32.0));
+    path.line_to((112.0, 32.0));
+    path
+}
+
+fn sharp_corner() -> BezPath {
+    let mut path = BezPath::new();
+    path.move_to((16.0, 56.0));
+    path.line_to((64.0, 24.0));
+    path.line_to((112.0, 56.0));
+    path
+}
+
+#[test]
+fn dashes() {
+    let stroke = Stroke::new(8.0).with_dashes(0.0, [12.0, 8.0]);
+    assert_matches_vello_cpu(stroke, horizontal_line());
+}
+
+#[test]
+fn dash_offset() {
+    let stroke = Stroke::new(8.0).with_dashes(6.0, [12.0, 8.0]);
+    assert_matches_vello_cpu(stroke, horizontal_line());
+}
+
+#[test]
+fn odd_dash_pattern() {
+    let stroke = Stroke::new(8.0).with_dashes(0.0, [12.0, 8.0, 4.0]);
+    assert_matches_vello_cpu(stroke, horizontal_line());
+}
+
+#[test]
+fn miter_limit() {
+    let stroke = Stroke::new(8.0)
+        .with_join(Join::Miter)
+        .with_miter_limit(1.0);
+    assert_matches_vello_cpu(stroke, sharp_corner());
+}
+
+#[test]
+fn differing_caps() {
+    let stroke = Stroke::new(16.0)
+        .with_start_cap(Cap::Butt)
+        .with_end_cap(Cap::Round);
+    assert_matches_vello_cpu(stroke, horizontal_line());
+}
+
+#[test]
+fn dashes_with_differing_caps() {
+    let stroke = Stroke::new(8.0)
+        .with_start_cap(Cap::Square)
+        .with_end_cap(Cap::Butt)
+        .with_dashes(0.0, [12.0, 8.0]);
+    assert_matches_vello_cpu(stroke, horizontal_line());
+}
@@ -0,0 +1,87 @@
+//! Checks that text drawn from cached text blobs matches text drawn without the cache
+
+use anyrender::{Glyph, ImageRenderer, PaintScene};
+use anyrender_skia::SkiaImageRenderer;
+use kurbo::Affine;
+use peniko::{Blob, Color, Fill, FontData};
+use skia_safe::{Font, FontMgr, FontStyle};
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 64;
+const FONT_SIZE: f32 = 16.0;
+
+/// The system's default font and the glyph ids of a line of text in it
+fn default_font() -> Option<(FontData, Vec<u32>)> {
+    let typeface = FontMgr::new().legacy_make_typeface(None::<&str>, FontStyle::default())?;
+    let (data, index) = typeface.to_font_data()?;
+    let glyph_ids = Font::from_typeface(&typeface, FONT_SIZE)
+        .str_to_glyphs_vec("Hello")
+        .into_iter()
+        .map(u32::from)
+        .collect();
+    Some((FontData::new(Blob::from(data), index as u32), glyph_ids))
+}
+
+fn render(
+    renderer: &mut SkiaImageRenderer,
+    font: &FontData,
+    glyph_ids: &[u32],
+    x: f32,
+    y: f32,
+) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    renderer.render_to_vec(
+        |scene| {
+            let glyphs = glyph_ids.iter().enumerate().map(|(i, &id)| Glyph {
+                id,
+                x: x + i as f32 * 12.0,
+                y,
+            });
+            scene.draw_glyphs(
+                font,
+                FONT_SIZE,
+                false,
+                &[],
+                Fill::NonZero,
+                Color::BLACK,
+                1.0,
+                Affine::IDENTITY,
+                None,
+                glyphs,
+            );
+        },
+        &mut buffer,
+    );
+    buffer
+}
+
+#[test]
+fn cached_text_matches_uncached() {
+    let Some((font, glyph_ids)) = default_font() else {
+        eprintln!("Skipping test: no default font available");
+        return;
+    };
+
+    let mut renderer = SkiaImageRenderer::new(WIDTH, HEIGHT);
+    let uncached = render(&mut renderer, &font, &glyph_ids, 8.0, 32.0);
+    assert!(
+        uncached.iter().any(|&value| value != 0),
+        "no text was drawn"
+    );
+
+    let cached = render(&mut renderer, &font, &glyph_ids, 8.0, 32.0);
+    assert_eq!(cached, uncached);
+    assert_eq!(renderer.cache_stats().text_blobs, 1);
+
+    // A run which has moved reuses the cached blob
+    let moved_cached = render(&mut renderer, &font, &glyph_ids, 40.5, 48.0);
+    let moved_uncached = render(
+        &mut SkiaImageRenderer::new(WIDTH, HEIGHT),
+        &font,
+        &glyph_ids,
+        40.5,
+        48.0,
+    );
+    assert_eq!(moved_cached, moved_uncached);
+    assert_eq!(renderer.cache_stats().text_blobs, 1);
+}
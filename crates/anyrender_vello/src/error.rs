@@ -0,0 +1,54 @@
+//! Error type for rendering frames
+
+use std::error::Error;
+use std::fmt::Display;
+use wgpu::{PollError, SurfaceError};
+
+/// Errors that can occur when rendering a frame with [`crate::VelloWindowRenderer::try_render`].
+/// The frame is skipped when an error occurs.
+#[derive(Debug)]
+pub enum VelloRenderError {
+    /// The surface couldn't be rendered to. Lost, outdated and timed out surfaces are handled by
+    /// skipping the frame rather than returning an error.
+    SurfaceError(SurfaceError),
+    /// Vello failed to render the scene
+    VelloError(vello::Error),
+    /// Wgpu failed to poll the device while waiting for a frame to finish
+    PollError(PollError),
+}
+
+impl Display for VelloRenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SurfaceError(inner) => {
+                writeln!(f, "Couldn't render to surface: {:#}", inner)
+            }
+            Self::VelloError(inner) => {
+                writeln!(f, "Couldn't render scene: {:#}", inner)
+            }
+            Self::PollError(inner) => {
+                writeln!(f, "Couldn't poll the device: {:#}", inner)
+            }
+        }
+    }
+}
+
+impl Error for VelloRenderError {}
+
+impl From<SurfaceError> for VelloRenderError {
+    fn from(value: SurfaceError) -> Self {
+        Self::SurfaceError(value)
+    }
+}
+
+impl From<vello::Error> for VelloRenderError {
+    fn from(value: vello::Error) -> Self {
+        Self::VelloError(value)
+    }
+}
+
+impl From<PollError> for VelloRenderError {
+    fn from(value: PollError) -> Self {
+        Self::PollError(value)
+    }
+}
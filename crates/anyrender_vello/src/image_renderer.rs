@@ -1,57 +1,329 @@
 use anyrender::ImageRenderer;
+use peniko::Color;
 use rustc_hash::FxHashMap;
-use vello::{Renderer as VelloRenderer, RendererOptions, Scene as VelloScene};
+use std::sync::{Arc, Mutex, MutexGuard};
+use vello::{
+    AaConfig, AaSupport, RenderParams, Renderer as VelloRenderer, RendererOptions,
+    Scene as VelloScene,
+};
 use wgpu::TextureUsages;
-use wgpu_context::{BufferRenderer, BufferRendererConfig, WGPUContext};
+use wgpu_context::{BufferRenderer, BufferRendererConfig, DeviceHandle, WGPUContext};
 
-use crate::{DEFAULT_THREADS, VelloScenePainter};
+use crate::{DEFAULT_THREADS, VelloScenePainter, aa_support, use_cpu_shaders};
 
-pub struct VelloImageRenderer {
-    buffer_renderer: BufferRenderer,
-    vello_renderer: VelloRenderer,
-    scene: VelloScene,
+#[derive(Clone, Debug)]
+pub struct VelloImageRendererOptions {
+    /// The color that the image is cleared to before drawing the scene
+    pub base_color: Color,
+    pub antialiasing_method: AaConfig,
+    /// Run Vello's compute stages on the CPU. If `None`, they run on the CPU only when no GPU is available
+    /// and a fallback (software) adapter is used.
+    pub use_cpu: Option<bool>,
 }
 
-impl ImageRenderer for VelloImageRenderer {
-    type ScenePainter<'a>
-        = VelloScenePainter<'a, 'a>
-    where
-        Self: 'a;
+impl Default for VelloImageRendererOptions {
+    fn default() -> Self {
+        Self {
+            base_color: Color::TRANSPARENT,
+            antialiasing_method: AaConfig::Area,
+            use_cpu: None,
+        }
+    }
+}
 
-    fn new(width: u32, height: u32) -> Self {
-        // Create WGPUContext
-        let mut context = WGPUContext::new();
-
-        // Create wgpu_context::BufferRenderer
-        let buffer_renderer =
-            pollster::block_on(context.create_buffer_renderer(BufferRendererConfig {
-                width,
-                height,
-                usage: TextureUsages::STORAGE_BINDING,
-            }))
-            .expect("No compatible device found");
-
-        // Create vello::Renderer
-        let vello_renderer = VelloRenderer::new(
-            buffer_renderer.device(),
+/// The maximum number of idle `BufferRenderer`s kept by a [`VelloImageRendererPool`]
+const MAX_IDLE_BUFFER_RENDERERS: usize = 8;
+
+/// A `vello::Renderer` along with the device and antialiasing methods that it was created for
+struct PooledVelloRenderer {
+    renderer: VelloRenderer,
+    device_handle: DeviceHandle,
+    antialiasing_support: AaSupport,
+}
+
+impl PooledVelloRenderer {
+    fn new(
+        device_handle: &DeviceHandle,
+        antialiasing_support: AaSupport,
+        use_cpu: Option<bool>,
+    ) -> Self {
+        let renderer = VelloRenderer::new(
+            &device_handle.device,
             RendererOptions {
-                use_cpu: false,
+                use_cpu: use_cpu_shaders(use_cpu, device_handle),
                 num_init_threads: DEFAULT_THREADS,
-                antialiasing_support: vello::AaSupport::area_only(),
+                antialiasing_support,
                 pipeline_cache: None,
             },
         )
         .expect("Got non-Send/Sync error from creating renderer");
+        Self {
+            renderer,
+            device_handle: device_handle.clone(),
+            antialiasing_support,
+        }
+    }
+
+    fn supports(&self, device_handle: &DeviceHandle, required: AaSupport) -> bool {
+        let supported = self.antialiasing_support;
+        self.device_handle.device == device_handle.device
+            && (supported.area || !required.area)
+            && (supported.msaa8 || !required.msaa8)
+            && (supported.msaa16 || !required.msaa16)
+    }
+}
+
+struct PoolState {
+    wgpu_context: WGPUContext,
+    config: VelloImageRendererOptions,
+    // Vello renderers which aren't currently rendering. Each render takes one (creating one if none are
+    // idle) and returns it afterwards, so image renderers can render concurrently without recompiling
+    // pipelines once the pool has warmed up.
+    idle_vello_renderers: Vec<PooledVelloRenderer>,
+    // The antialiasing methods that have been used in the pool. New Vello renderers support all of them.
+    antialiasing_support: AaSupport,
+    // Buffer renderers from dropped image renderers, which are reused by new image renderers
+    idle_buffer_renderers: Vec<BufferRenderer>,
+}
+
+impl PoolState {
+    fn acquire_buffer_renderer(&mut self, width: u32, height: u32) -> BufferRenderer {
+        // Drop idle buffer renderers whose device has been lost since they were released
+        let wgpu_context = &self.wgpu_context;
+        self.idle_buffer_renderers
+            .retain(|r| !wgpu_context.is_device_lost(&r.device_handle));
 
+        // Prefer a buffer renderer of the same size, then any buffer renderer (which will be resized)
+        let idle_index = self
+            .idle_buffer_renderers
+            .iter()
+            .position(|r| r.size().width == width && r.size().height == height)
+            .or_else(|| self.idle_buffer_renderers.len().checked_sub(1));
+        if let Some(idle_index) = idle_index {
+            let mut buffer_renderer = self.idle_buffer_renderers.swap_remove(idle_index);
+            buffer_renderer.resize(width, height);
+            return buffer_renderer;
+        }
+
+        pollster::block_on(
+            self.wgpu_context
+                .create_buffer_renderer(BufferRendererConfig {
+                    width,
+                    height,
+                    usage: TextureUsages::STORAGE_BINDING,
+                }),
+        )
+        .expect("No compatible device found")
+    }
+
+    /// Takes an idle Vello renderer which supports `antialiasing_method` on the device. If there is none,
+    /// records that the method is used so that a new renderer can be created for it.
+    fn take_idle_vello_renderer(
+        &mut self,
+        device_handle: &DeviceHandle,
+        antialiasing_method: AaConfig,
+    ) -> Option<PooledVelloRenderer> {
+        let wgpu_context = &self.wgpu_context;
+        self.idle_vello_renderers
+            .retain(|r| !wgpu_context.is_device_lost(&r.device_handle));
+
+        let required = aa_support(antialiasing_method);
+        if let Some(index) = self
+            .idle_vello_renderers
+            .iter()
+            .position(|r| r.supports(device_handle, required))
+        {
+            return Some(self.idle_vello_renderers.swap_remove(index));
+        }
+
+        // Replace an idle renderer which lacks support for the method, so that the number of renderers
+        // stays bounded by the number of concurrent renders
+        if let Some(index) = self
+            .idle_vello_renderers
+            .iter()
+            .position(|r| r.device_handle.device == device_handle.device)
+        {
+            self.idle_vello_renderers.swap_remove(index);
+        }
+        let supported = self.antialiasing_support;
+        self.antialiasing_support = AaSupport {
+            area: supported.area || required.area,
+            msaa8: supported.msaa8 || required.msaa8,
+            msaa16: supported.msaa16 || required.msaa16,
+        };
+        None
+    }
+
+    fn release_vello_renderer(&mut self, vello_renderer: PooledVelloRenderer) {
+        if !self
+            .wgpu_context
+            .is_device_lost(&vello_renderer.device_handle)
+        {
+            self.idle_vello_renderers.push(vello_renderer);
+        }
+    }
+
+    fn release_buffer_renderer(&mut self, buffer_renderer: BufferRenderer) {
+        if self.idle_buffer_renderers.len() < MAX_IDLE_BUFFER_RENDERERS
+            && !self
+                .wgpu_context
+                .is_device_lost(&buffer_renderer.device_handle)
+        {
+            self.idle_buffer_renderers.push(buffer_renderer);
+        }
+    }
+}
+
+/// A pool of GPU state which is shared between [`VelloImageRenderer`]s.
+///
+/// Image renderers created from the same pool share a `WGPUContext` (and therefore device) and a set of
+/// `vello::Renderer`s. Each render borrows an idle `vello::Renderer`, so shader pipelines are only compiled
+/// when more image renderers render at once than ever before. The `BufferRenderer`s of dropped image
+/// renderers are kept and reused by subsequently created image renderers.
+///
+/// Pools are cheap to clone and can be sent between threads. The shared state lives until the last pool
+/// and image renderer are dropped.
+#[derive(Clone)]
+pub struct VelloImageRendererPool {
+    state: Arc<Mutex<PoolState>>,
+}
+
+impl VelloImageRendererPool {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self::with_options(VelloImageRendererOptions::default())
+    }
+
+    /// Create a pool whose image renderers use the specified options
+    pub fn with_options(config: VelloImageRendererOptions) -> Self {
+        Self::with_wgpu_context(WGPUContext::new(), config)
+    }
+
+    /// Create a pool which renders using an existing device rather than creating its own
+    pub fn with_device_handle(
+        device_handle: DeviceHandle,
+        config: VelloImageRendererOptions,
+    ) -> Self {
+        Self::with_wgpu_context(WGPUContext::with_device_handle(device_handle), config)
+    }
+
+    fn with_wgpu_context(wgpu_context: WGPUContext, config: VelloImageRendererOptions) -> Self {
         Self {
-            buffer_renderer,
-            vello_renderer,
+            state: Arc::new(Mutex::new(PoolState {
+                wgpu_context,
+                config,
+                idle_vello_renderers: Vec::new(),
+                antialiasing_support: AaSupport::area_only(),
+                idle_buffer_renderers: Vec::new(),
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Takes an idle Vello renderer which supports `antialiasing_method`, or creates one
+    fn acquire_vello_renderer(
+        &self,
+        device_handle: &DeviceHandle,
+        antialiasing_method: AaConfig,
+    ) -> PooledVelloRenderer {
+        let (antialiasing_support, use_cpu) = {
+            let mut state = self.state();
+            if let Some(vello_renderer) =
+                state.take_idle_vello_renderer(device_handle, antialiasing_method)
+            {
+                return vello_renderer;
+            }
+            (state.antialiasing_support, state.config.use_cpu)
+        };
+
+        // The pool isn't locked while compiling pipelines, so other renders can continue
+        PooledVelloRenderer::new(device_handle, antialiasing_support, use_cpu)
+    }
+
+    /// Create an image renderer which shares this pool's device and renderers
+    pub fn create_image_renderer(&self, width: u32, height: u32) -> VelloImageRenderer {
+        let (buffer_renderer, config) = {
+            let mut state = self.state();
+            let buffer_renderer = state.acquire_buffer_renderer(width, height);
+            (buffer_renderer, state.config.clone())
+        };
+
+        // Make sure that a Vello renderer is ready, so that the first render doesn't compile pipelines
+        let vello_renderer =
+            self.acquire_vello_renderer(&buffer_renderer.device_handle, config.antialiasing_method);
+        self.state().release_vello_renderer(vello_renderer);
+
+        VelloImageRenderer {
+            buffer_renderer: Some(buffer_renderer),
+            pool: self.clone(),
             scene: VelloScene::new(),
+            config,
         }
     }
+}
+
+pub struct VelloImageRenderer {
+    // Only `None` while being dropped
+    buffer_renderer: Option<BufferRenderer>,
+    pool: VelloImageRendererPool,
+    scene: VelloScene,
+    config: VelloImageRendererOptions,
+}
+
+impl VelloImageRenderer {
+    /// Create a renderer (with its own pool) which uses the specified options
+    pub fn with_options(width: u32, height: u32, config: VelloImageRendererOptions) -> Self {
+        VelloImageRendererPool::with_options(config).create_image_renderer(width, height)
+    }
+
+    /// Create a renderer which renders using an existing device rather than creating its own
+    pub fn with_device_handle(
+        device_handle: DeviceHandle,
+        width: u32,
+        height: u32,
+        config: VelloImageRendererOptions,
+    ) -> Self {
+        VelloImageRendererPool::with_device_handle(device_handle, config)
+            .create_image_renderer(width, height)
+    }
+
+    /// Change the antialiasing method. Takes effect from the next render.
+    ///
+    /// The `vello::Renderer`s are shared by all image renderers in the pool. If no idle renderer has
+    /// compiled pipelines for the method yet then one is replaced on the next render by a renderer which
+    /// supports every method used in the pool so far. This compiles all of its shader pipelines, which is
+    /// slow, but only happens the first time each method is used by each concurrently rendering image
+    /// renderer.
+    pub fn set_antialiasing_method(&mut self, antialiasing_method: AaConfig) {
+        self.config.antialiasing_method = antialiasing_method;
+    }
+
+    /// The pool that this renderer's GPU state is shared with. Can be used to create more image
+    /// renderers which share the same device and renderer.
+    pub fn pool(&self) -> &VelloImageRendererPool {
+        &self.pool
+    }
+
+    fn buffer_renderer(&self) -> &BufferRenderer {
+        self.buffer_renderer.as_ref().unwrap()
+    }
+}
+
+impl ImageRenderer for VelloImageRenderer {
+    type ScenePainter<'a>
+        = VelloScenePainter<'a, 'a>
+    where
+        Self: 'a;
+
+    fn new(width: u32, height: u32) -> Self {
+        VelloImageRendererPool::new().create_image_renderer(width, height)
+    }
 
     fn resize(&mut self, width: u32, height: u32) {
-        self.buffer_renderer.resize(width, height);
+        self.buffer_renderer.as_mut().unwrap().resize(width, height);
     }
 
     fn reset(&mut self) {
@@ -63,9 +335,9 @@ impl ImageRenderer for VelloImageRenderer {
         draw_fn: F,
         cpu_buffer: &mut Vec<u8>,
     ) {
-        let size = self.buffer_renderer.size();
+        let size = self.buffer_renderer().size();
         cpu_buffer.clear();
-        cpu_buffer.reserve((size.width * size.height * 4) as usize);
+        cpu_buffer.resize((size.width * size.height * 4) as usize, 0);
         self.render(draw_fn, cpu_buffer);
     }
 
@@ -74,31 +346,57 @@ impl ImageRenderer for VelloImageRenderer {
         draw_fn: F,
         cpu_buffer: &mut [u8],
     ) {
+        let buffer_renderer = self.buffer_renderer.as_mut().unwrap();
+
+        // The pool isn't locked while rendering, so `draw_fn` may create, render with or drop other
+        // renderers from the same pool, and other threads can render concurrently
+        let mut vello_renderer = self.pool.acquire_vello_renderer(
+            &buffer_renderer.device_handle,
+            self.config.antialiasing_method,
+        );
+
         draw_fn(&mut VelloScenePainter {
             inner: &mut self.scene,
-            renderer: Some(&mut self.vello_renderer),
+            renderer: Some(&mut vello_renderer.renderer),
             custom_paint_sources: Some(&mut FxHashMap::default()),
         });
 
-        let size = self.buffer_renderer.size();
-        self.vello_renderer
+        let size = buffer_renderer.size();
+        vello_renderer
+            .renderer
             .render_to_texture(
-                self.buffer_renderer.device(),
-                self.buffer_renderer.queue(),
+                buffer_renderer.device(),
+                buffer_renderer.queue(),
                 &self.scene,
-                &self.buffer_renderer.target_texture_view(),
-                &vello::RenderParams {
-                    base_color: vello::peniko::Color::TRANSPARENT,
+                &buffer_renderer.target_texture_view(),
+                &RenderParams {
+                    base_color: self.config.base_color,
                     width: size.width,
                     height: size.height,
-                    antialiasing_method: vello::AaConfig::Area,
+                    antialiasing_method: self.config.antialiasing_method,
                 },
             )
             .expect("Got non-Send/Sync error from rendering");
 
-        self.buffer_renderer.copy_texture_to_buffer(cpu_buffer);
+        buffer_renderer.copy_texture_to_buffer(cpu_buffer);
+        buffer_renderer.end_frame();
 
         // Empty the Vello scene (memory optimisation)
         self.scene.reset();
+
+        self.pool.state().release_vello_renderer(vello_renderer);
+    }
+
+    fn set_base_color(&mut self, color: Color) {
+        self.config.base_color = color;
+    }
+}
+
+impl Drop for VelloImageRenderer {
+    fn drop(&mut self) {
+        // Return the buffer renderer to the pool so that it can be reused
+        if let Some(buffer_renderer) = self.buffer_renderer.take() {
+            self.pool.state().release_buffer_renderer(buffer_renderer);
+        }
     }
 }
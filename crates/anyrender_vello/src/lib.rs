@@ -1,20 +1,51 @@
 //! A [`vello`] backend for the [`anyrender`] 2D drawing abstraction
+mod error;
 mod image_renderer;
+mod renderer_handle;
 mod scene;
+mod texture_renderer;
 mod window_renderer;
 
 pub mod custom_paint_source;
 
 pub use custom_paint_source::*;
-pub use image_renderer::VelloImageRenderer;
+pub use error::VelloRenderError;
+pub use image_renderer::{VelloImageRenderer, VelloImageRendererOptions, VelloImageRendererPool};
+pub use renderer_handle::VelloRendererHandle;
 pub use scene::VelloScenePainter;
-pub use window_renderer::{VelloRendererOptions, VelloWindowRenderer};
+pub use texture_renderer::{TargetLoadOp, VelloTextureRenderer};
+pub use window_renderer::{FramePacing, VelloRendererOptions, VelloWindowRenderer};
 
 pub use wgpu;
 
 use std::num::NonZeroUsize;
+use vello::{AaConfig, AaSupport};
+use wgpu_context::DeviceHandle;
 
 #[cfg(target_os = "macos")]
 const DEFAULT_THREADS: Option<NonZeroUsize> = NonZeroUsize::new(1);
 #[cfg(not(target_os = "macos"))]
 const DEFAULT_THREADS: Option<NonZeroUsize> = None;
+
+/// The antialiasing support required to render with a single antialiasing method
+pub(crate) fn aa_support(antialiasing_method: AaConfig) -> AaSupport {
+    match antialiasing_method {
+        AaConfig::Area => AaSupport::area_only(),
+        AaConfig::Msaa8 => AaSupport {
+            area: false,
+            msaa8: true,
+            msaa16: false,
+        },
+        AaConfig::Msaa16 => AaSupport {
+            area: false,
+            msaa8: false,
+            msaa16: true,
+        },
+    }
+}
+
+/// Whether Vello's compute stages should run on the CPU. If not specified, they run on the CPU
+/// when rendering with a fallback (software) adapter.
+pub(crate) fn use_cpu_shaders(use_cpu: Option<bool>, device_handle: &DeviceHandle) -> bool {
+    use_cpu.unwrap_or_else(|| device_handle.is_software())
+}
@@ -0,0 +1,229 @@
+use rustc_hash::FxHashMap;
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::atomic::{self, AtomicU64},
+};
+use vello::{AaSupport, Renderer as VelloRenderer, RendererOptions};
+use wgpu::Features;
+use wgpu_context::{DeviceHandle, WGPUContext};
+
+use crate::{
+    CustomPaintSource, DEFAULT_THREADS, VelloRendererOptions, VelloWindowRenderer, use_cpu_shaders,
+};
+
+static PAINT_SOURCE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A `vello::Renderer` along with the device it was created for
+struct DeviceRenderer {
+    device_handle: DeviceHandle,
+    renderer: VelloRenderer,
+    /// The number of active windows using this renderer
+    active_windows: usize,
+}
+
+/// State which is shared between all windows created from a [`VelloRendererHandle`]
+pub(crate) struct SharedRendererState {
+    pub(crate) wgpu_context: WGPUContext,
+    pub(crate) config: VelloRendererOptions,
+    // A renderer for each device that active windows are rendering with. This is almost always a single
+    // renderer, but windows on different GPUs may require different devices.
+    renderers: Vec<DeviceRenderer>,
+    pub(crate) custom_paint_sources: FxHashMap<u64, Box<dyn CustomPaintSource>>,
+    // The device that custom paint sources are currently resumed with (if any)
+    paint_source_device: Option<DeviceHandle>,
+}
+
+impl SharedRendererState {
+    /// Gets the renderer for a device, creating it (and resuming custom paint sources) if necessary.
+    /// The renderer's active window count is incremented.
+    pub(crate) fn acquire_renderer(&mut self, device_handle: &DeviceHandle) {
+        // Drop renderers for lost devices
+        self.renderers
+            .retain(|r| !self.wgpu_context.is_device_lost(&r.device_handle));
+
+        match self
+            .renderers
+            .iter_mut()
+            .find(|r| r.device_handle.device == device_handle.device)
+        {
+            Some(device_renderer) => device_renderer.active_windows += 1,
+            None => {
+                let renderer = self.create_renderer(device_handle);
+                self.renderers.push(DeviceRenderer {
+                    device_handle: device_handle.clone(),
+                    renderer,
+                    active_windows: 1,
+                });
+            }
+        }
+
+        // Resume custom paint sources (suspending them first if their device has been lost)
+        if self
+            .paint_source_device
+            .as_ref()
+            .is_some_and(|device_handle| self.wgpu_context.is_device_lost(device_handle))
+        {
+            self.suspend_paint_sources();
+        }
+        if self.paint_source_device.is_none() {
+            for source in self.custom_paint_sources.values_mut() {
+                source.resume(device_handle)
+            }
+            self.paint_source_device = Some(device_handle.clone());
+        }
+    }
+
+    /// Decrements the active window count of the renderer for a device. The renderer is dropped
+    /// once no windows are using it, and custom paint sources are suspended once no renderers remain.
+    pub(crate) fn release_renderer(&mut self, device_handle: &DeviceHandle) {
+        if let Some(device_renderer) = self
+            .renderers
+            .iter_mut()
+            .find(|r| r.device_handle.device == device_handle.device)
+        {
+            device_renderer.active_windows -= 1;
+        }
+        self.renderers.retain(|r| r.active_windows > 0);
+
+        if self.renderers.is_empty() {
+            self.suspend_paint_sources();
+        }
+    }
+
+    /// The renderer for a device, along with the custom paint sources if they are resumed with that device
+    pub(crate) fn renderer_mut(
+        &mut self,
+        device_handle: &DeviceHandle,
+    ) -> Option<(
+        &mut VelloRenderer,
+        Option<&mut FxHashMap<u64, Box<dyn CustomPaintSource>>>,
+    )> {
+        let device_renderer = self
+            .renderers
+            .iter_mut()
+            .find(|r| r.device_handle.device == device_handle.device)?;
+        let custom_paint_sources = self
+            .paint_source_device
+            .as_ref()
+            .is_some_and(|d| d.device == device_handle.device)
+            .then_some(&mut self.custom_paint_sources);
+        Some((&mut device_renderer.renderer, custom_paint_sources))
+    }
+
+    fn create_renderer(&self, device_handle: &DeviceHandle) -> VelloRenderer {
+        // Load pipeline cache (if enabled)
+        let pipeline_cache = self
+            .config
+            .pipeline_cache
+            .as_ref()
+            .and_then(|manager| manager.load(device_handle));
+
+        // Create vello::Renderer
+        let renderer = VelloRenderer::new(
+            &device_handle.device,
+            RendererOptions {
+                antialiasing_support: AaSupport::all(),
+                use_cpu: use_cpu_shaders(self.config.use_cpu, device_handle),
+                num_init_threads: DEFAULT_THREADS,
+                pipeline_cache: pipeline_cache.as_ref().map(|c| c.cache.clone()),
+            },
+        )
+        .unwrap();
+
+        // Persist pipeline cache now that all of Vello's pipelines have been compiled
+        if let Some(pipeline_cache) = &pipeline_cache {
+            if let Err(err) = pipeline_cache.save() {
+                eprintln!("WARNING: failed to save pipeline cache: {err}");
+            }
+        }
+
+        renderer
+    }
+
+    fn suspend_paint_sources(&mut self) {
+        if self.paint_source_device.take().is_some() {
+            for source in self.custom_paint_sources.values_mut() {
+                source.suspend()
+            }
+        }
+    }
+}
+
+/// A handle to a Vello renderer which can be shared between multiple windows.
+///
+/// All windows created using [`VelloRendererHandle::create_window_renderer`] share a single
+/// `WGPUContext` (and therefore device), `vello::Renderer` (including its shader pipelines and
+/// image cache) and set of custom paint sources. Each window has its own surface.
+///
+/// Handles are cheap to clone. The shared state lives until the last handle and window renderer are dropped.
+/// As the state is borrowed while rendering, the handle must not be used from within a window's `draw_fn`.
+#[derive(Clone)]
+pub struct VelloRendererHandle {
+    pub(crate) state: Rc<RefCell<SharedRendererState>>,
+}
+
+impl VelloRendererHandle {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self::with_options(VelloRendererOptions::default())
+    }
+
+    pub fn with_options(config: VelloRendererOptions) -> Self {
+        let features = config.features.unwrap_or_default()
+            | Features::CLEAR_TEXTURE
+            | Features::PIPELINE_CACHE;
+        let mut wgpu_context =
+            WGPUContext::with_features_and_limits(Some(features), config.limits.clone());
+        wgpu_context.set_adapter_selection(config.adapter_selection.clone());
+        Self::with_wgpu_context(wgpu_context, config)
+    }
+
+    /// Create a renderer which renders using an existing device rather than creating its own.
+    ///
+    /// `features` and `limits` in `config` are ignored as the device has already been created.
+    pub fn with_device_handle(device_handle: DeviceHandle, config: VelloRendererOptions) -> Self {
+        Self::with_wgpu_context(WGPUContext::with_device_handle(device_handle), config)
+    }
+
+    fn with_wgpu_context(wgpu_context: WGPUContext, config: VelloRendererOptions) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(SharedRendererState {
+                wgpu_context,
+                config,
+                renderers: Vec::new(),
+                custom_paint_sources: FxHashMap::default(),
+                paint_source_device: None,
+            })),
+        }
+    }
+
+    /// Create a window renderer which shares this handle's device, renderer and custom paint sources.
+    ///
+    /// The window renderer is created with this handle's options. Per-window options (such as the
+    /// present mode) can be changed on the returned window renderer.
+    pub fn create_window_renderer(&self) -> VelloWindowRenderer {
+        let config = self.state.borrow().config.clone();
+        VelloWindowRenderer::with_renderer_handle(self.clone(), config)
+    }
+
+    /// Register a custom paint source, which can be used by all windows created from this handle
+    pub fn register_custom_paint_source(&self, mut source: Box<dyn CustomPaintSource>) -> u64 {
+        let mut state = self.state.borrow_mut();
+        if let Some(device_handle) = &state.paint_source_device {
+            source.resume(device_handle);
+        }
+        let id = PAINT_SOURCE_ID.fetch_add(1, atomic::Ordering::SeqCst);
+        state.custom_paint_sources.insert(id, source);
+
+        id
+    }
+
+    pub fn unregister_custom_paint_source(&self, id: u64) {
+        let mut state = self.state.borrow_mut();
+        if let Some(mut source) = state.custom_paint_sources.remove(&id) {
+            source.suspend();
+            drop(source);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use peniko::Color;
+use rustc_hash::FxHashMap;
+use vello::{
+    AaConfig, Error as VelloError, RenderParams, Renderer as VelloRenderer, RendererOptions,
+    Scene as VelloScene,
+};
+use wgpu::{
+    BlendState, CommandEncoderDescriptor, Device, Queue, TextureFormat, TextureUsages, TextureView,
+    util::{TextureBlitter, TextureBlitterBuilder},
+};
+
+use crate::{DEFAULT_THREADS, VelloScenePainter, aa_support};
+
+/// What to do with the existing contents of the target texture when rendering
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetLoadOp {
+    /// Clear the target to the specified color before drawing the scene
+    Clear(Color),
+    /// Draw the scene on top of the existing contents of the target
+    Load,
+}
+
+/// Renders anyrender scenes into caller-owned `wgpu::TextureView`s using a caller-owned `Device` and `Queue`.
+///
+/// This is intended for embedding anyrender output (HUDs, in-world UI panels, etc) into an existing wgpu
+/// render graph. Unlike [`VelloWindowRenderer`](crate::VelloWindowRenderer) and
+/// [`VelloImageRenderer`](crate::VelloImageRenderer) it does not create or manage any wgpu state
+/// of its own other than the `vello::Renderer` and an intermediate texture.
+///
+/// Targets with the `Rgba8Unorm` format and `STORAGE_BINDING` usage are rendered to directly when using
+/// [`TargetLoadOp::Clear`]. All other targets are rendered via an intermediate texture which is then
+/// blitted onto the target, which requires the target to have the `RENDER_ATTACHMENT` usage.
+pub struct VelloTextureRenderer {
+    renderer: VelloRenderer,
+    scene: VelloScene,
+    antialiasing_method: AaConfig,
+    intermediate_texture: Option<TextureView>,
+    // Blitters keyed by target format and whether they blend onto the existing contents
+    blitters: FxHashMap<(TextureFormat, bool), TextureBlitter>,
+}
+
+impl VelloTextureRenderer {
+    /// Create a renderer which uses area antialiasing
+    pub fn new(device: &Device) -> Result<Self, VelloError> {
+        Self::with_antialiasing(device, AaConfig::Area)
+    }
+
+    /// Create a renderer which uses the specified antialiasing method
+    pub fn with_antialiasing(
+        device: &Device,
+        antialiasing_method: AaConfig,
+    ) -> Result<Self, VelloError> {
+        let renderer = VelloRenderer::new(
+            device,
+            RendererOptions {
+                use_cpu: false,
+                num_init_threads: DEFAULT_THREADS,
+                antialiasing_support: aa_support(antialiasing_method),
+                pipeline_cache: None,
+            },
+        )?;
+
+        Ok(Self {
+            renderer,
+            scene: VelloScene::new(),
+            antialiasing_method,
+            intermediate_texture: None,
+            blitters: FxHashMap::default(),
+        })
+    }
+
+    /// Paint a scene using `draw_fn` and render it into `target`.
+    ///
+    /// The scene is rendered at the full size of the target's texture.
+    pub fn render<F: FnOnce(&mut VelloScenePainter<'_, '_>)>(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        target: &TextureView,
+        load_op: TargetLoadOp,
+        draw_fn: F,
+    ) -> Result<(), VelloError> {
+        draw_fn(&mut VelloScenePainter {
+            inner: &mut self.scene,
+            renderer: Some(&mut self.renderer),
+            custom_paint_sources: None,
+        });
+
+        let target_texture = target.texture();
+        let width = target_texture.width();
+        let height = target_texture.height();
+        let format = target_texture.format();
+
+        let base_color = match load_op {
+            TargetLoadOp::Clear(color) => color,
+            TargetLoadOp::Load => Color::TRANSPARENT,
+        };
+        let render_params = RenderParams {
+            base_color,
+            width,
+            height,
+            antialiasing_method: self.antialiasing_method,
+        };
+
+        let can_render_directly = matches!(load_op, TargetLoadOp::Clear(_))
+            && format == TextureFormat::Rgba8Unorm
+            && target_texture
+                .usage()
+                .contains(TextureUsages::STORAGE_BINDING);
+
+        let result = if can_render_directly {
+            self.renderer
+                .render_to_texture(device, queue, &self.scene, target, &render_params)
+        } else {
+            self.render_via_intermediate_texture(device, queue, target, load_op, &render_params)
+        };
+
+        // Empty the Vello scene (memory optimisation)
+        self.scene.reset();
+
+        result
+    }
+
+    fn render_via_intermediate_texture(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        target: &TextureView,
+        load_op: TargetLoadOp,
+        render_params: &RenderParams,
+    ) -> Result<(), VelloError> {
+        // (Re)create the intermediate texture if it is missing or the wrong size
+        let intermediate_texture = match &self.intermediate_texture {
+            Some(view)
+                if view.texture().width() == render_params.width
+                    && view.texture().height() == render_params.height =>
+            {
+                view.clone()
+            }
+            _ => {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("VelloTextureRenderer intermediate texture"),
+                    size: wgpu::Extent3d {
+                        width: render_params.width,
+                        height: render_params.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+                    format: TextureFormat::Rgba8Unorm,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                self.intermediate_texture = Some(view.clone());
+                view
+            }
+        };
+
+        self.renderer.render_to_texture(
+            device,
+            queue,
+            &self.scene,
+            &intermediate_texture,
+            render_params,
+        )?;
+
+        // Vello outputs unpremultiplied colors, so regular alpha blending is used when drawing
+        // on top of the existing contents of the target
+        let format = target.texture().format();
+        let blend = matches!(load_op, TargetLoadOp::Load);
+        let blitter = self.blitters.entry((format, blend)).or_insert_with(|| {
+            let builder = TextureBlitterBuilder::new(device, format);
+            if blend {
+                builder.blend_state(BlendState::ALPHA_BLENDING).build()
+            } else {
+                builder.build()
+            }
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("VelloTextureRenderer blit"),
+        });
+        blitter.copy(device, &mut encoder, &intermediate_texture, target);
+        queue.submit([encoder.finish()]);
+
+        Ok(())
+    }
+}
@@ -1,28 +1,56 @@
 use anyrender::{WindowHandle, WindowRenderer};
 use debug_timer::debug_timer;
 use peniko::Color;
-use rustc_hash::FxHashMap;
-use std::sync::{
-    Arc,
-    atomic::{self, AtomicU64},
+use std::{collections::VecDeque, sync::Arc};
+use vello::{AaConfig, RenderParams, Scene as VelloScene};
+use wgpu::{
+    CompositeAlphaMode, Features, Limits, PollError, PresentMode, SubmissionIndex, SurfaceError,
+    TextureFormat, TextureUsages,
 };
-use vello::{
-    AaConfig, AaSupport, RenderParams, Renderer as VelloRenderer, RendererOptions,
-    Scene as VelloScene,
-};
-use wgpu::{Features, Limits, PresentMode, TextureFormat, TextureUsages};
 use wgpu_context::{
-    DeviceHandle, SurfaceRenderer, SurfaceRendererConfiguration, TextureConfiguration, WGPUContext,
+    AdapterSelection, DeviceHandle, PipelineCacheManager, SurfaceColorSpace, SurfaceRenderer,
+    SurfaceRendererConfiguration, TextureConfiguration,
 };
 
-use crate::{CustomPaintSource, DEFAULT_THREADS, VelloScenePainter};
-
-static PAINT_SOURCE_ID: AtomicU64 = AtomicU64::new(0);
+use crate::{CustomPaintSource, VelloRenderError, VelloRendererHandle, VelloScenePainter};
 
 // Simple struct to hold the state of the renderer
 struct ActiveRenderState {
-    renderer: VelloRenderer,
     render_surface: SurfaceRenderer<'static>,
+    // Submissions for frames which may still be executing on the GPU (only used with `FramePacing::Pipelined`)
+    frames_in_flight: VecDeque<SubmissionIndex>,
+}
+
+impl ActiveRenderState {
+    /// Waits for the GPU after a frame has been submitted, as configured by `frame_pacing`
+    fn wait_for_gpu(
+        &mut self,
+        frame_pacing: FramePacing,
+        desired_maximum_frame_latency: u32,
+    ) -> Result<(), PollError> {
+        let device = self.render_surface.device();
+        match frame_pacing {
+            FramePacing::Blocking => {
+                device.poll(wgpu::PollType::Wait)?;
+            }
+            FramePacing::Pipelined => {
+                // An empty submission returns an index which covers all work submitted for this frame
+                let submission_index = self.render_surface.queue().submit([]);
+                self.frames_in_flight.push_back(submission_index);
+
+                // Only block once too many frames are queued up on the GPU
+                let max_frames_in_flight = desired_maximum_frame_latency.max(1) as usize;
+                while self.frames_in_flight.len() > max_frames_in_flight {
+                    let oldest = self.frames_in_flight.pop_front().unwrap();
+                    device.poll(wgpu::PollType::WaitForSubmissionIndex(oldest))?;
+                }
+
+                // Make progress on completed work (e.g. invoke callbacks) without blocking
+                device.poll(wgpu::PollType::Poll)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -40,12 +68,43 @@ impl RenderState {
     }
 }
 
+/// How the CPU waits for the GPU at the end of each frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FramePacing {
+    /// Wait for the GPU to finish executing each frame before `render` returns
+    #[default]
+    Blocking,
+    /// Allow the next frame to be painted and encoded while the GPU is still executing previous frames.
+    /// The number of frames in flight is bounded by `desired_maximum_frame_latency`.
+    Pipelined,
+}
+
 #[derive(Clone)]
 pub struct VelloRendererOptions {
     pub features: Option<Features>,
     pub limits: Option<Limits>,
     pub base_color: Color,
     pub antialiasing_method: AaConfig,
+    /// Persist compiled shader pipelines to disk to speed up subsequent launches
+    pub pipeline_cache: Option<PipelineCacheManager>,
+    /// Presentation mode of the surface. Can be changed at runtime using [`VelloWindowRenderer::set_present_mode`]
+    pub present_mode: PresentMode,
+    /// Desired maximum number of frames that the presentation engine should queue in advance
+    pub desired_maximum_frame_latency: u32,
+    /// How the alpha channel of the surface should be handled during compositing
+    pub alpha_mode: CompositeAlphaMode,
+    /// The (sRGB) surface formats that may be used. The format that the surface prefers most will be used.
+    pub surface_formats: Vec<TextureFormat>,
+    /// The color space to present in. Falls back to `surface_formats` (sRGB) if the surface doesn't support it.
+    /// Vello renders 8-bit sRGB, so other color spaces don't provide HDR or a wider gamut (see [`SurfaceColorSpace`]).
+    pub output_color_space: SurfaceColorSpace,
+    /// Policy for choosing which GPU to render with
+    pub adapter_selection: AdapterSelection,
+    /// Whether to block on the GPU at the end of each frame
+    pub frame_pacing: FramePacing,
+    /// Run Vello's compute stages on the CPU. If `None`, they run on the CPU only when no GPU is available
+    /// and a fallback (software) adapter is used.
+    pub use_cpu: Option<bool>,
 }
 
 impl Default for VelloRendererOptions {
@@ -55,6 +114,15 @@ impl Default for VelloRendererOptions {
             limits: None,
             base_color: Color::WHITE,
             antialiasing_method: AaConfig::Msaa16,
+            pipeline_cache: None,
+            present_mode: PresentMode::AutoVsync,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: CompositeAlphaMode::Auto,
+            surface_formats: vec![TextureFormat::Rgba8Unorm, TextureFormat::Bgra8Unorm],
+            output_color_space: SurfaceColorSpace::Srgb,
+            adapter_selection: AdapterSelection::default(),
+            frame_pacing: FramePacing::Blocking,
+            use_cpu: None,
         }
     }
 }
@@ -66,11 +134,9 @@ pub struct VelloWindowRenderer {
     window_handle: Option<Arc<dyn WindowHandle>>,
 
     // Vello
-    wgpu_context: WGPUContext,
+    shared: VelloRendererHandle,
     scene: VelloScene,
     config: VelloRendererOptions,
-
-    custom_paint_sources: FxHashMap<u64, Box<dyn CustomPaintSource>>,
 }
 impl VelloWindowRenderer {
     #[allow(clippy::new_without_default)]
@@ -79,41 +145,177 @@ impl VelloWindowRenderer {
     }
 
     pub fn with_options(config: VelloRendererOptions) -> Self {
-        let features = config.features.unwrap_or_default()
-            | Features::CLEAR_TEXTURE
-            | Features::PIPELINE_CACHE;
+        VelloRendererHandle::with_options(config).create_window_renderer()
+    }
+
+    /// Create a renderer which renders using an existing device rather than creating its own.
+    ///
+    /// `features` and `limits` in `config` are ignored as the device has already been created.
+    pub fn with_device_handle(device_handle: DeviceHandle, config: VelloRendererOptions) -> Self {
+        VelloRendererHandle::with_device_handle(device_handle, config).create_window_renderer()
+    }
+
+    pub(crate) fn with_renderer_handle(
+        shared: VelloRendererHandle,
+        config: VelloRendererOptions,
+    ) -> Self {
         Self {
-            wgpu_context: WGPUContext::with_features_and_limits(
-                Some(features),
-                config.limits.clone(),
-            ),
+            shared,
             config,
             render_state: RenderState::Suspended,
             window_handle: None,
             scene: VelloScene::new(),
-            custom_paint_sources: FxHashMap::default(),
         }
     }
 
+    /// The handle to the renderer that this window renders with. Can be used to create more windows
+    /// which share the same renderer.
+    pub fn renderer_handle(&self) -> &VelloRendererHandle {
+        &self.shared
+    }
+
     pub fn current_device_handle(&self) -> Option<&DeviceHandle> {
         self.render_state.current_device_handle()
     }
 
-    pub fn register_custom_paint_source(&mut self, mut source: Box<dyn CustomPaintSource>) -> u64 {
-        if let Some(device_handle) = self.render_state.current_device_handle() {
-            source.resume(device_handle);
+    /// Register a custom paint source. It is shared with all other windows using the same [`VelloRendererHandle`].
+    pub fn register_custom_paint_source(&mut self, source: Box<dyn CustomPaintSource>) -> u64 {
+        self.shared.register_custom_paint_source(source)
+    }
+
+    pub fn unregister_custom_paint_source(&mut self, id: u64) {
+        self.shared.unregister_custom_paint_source(id)
+    }
+
+    /// The color space that the surface is being presented in (if the renderer is active)
+    pub fn output_color_space(&self) -> Option<SurfaceColorSpace> {
+        let RenderState::Active(state) = &self.render_state else {
+            return None;
+        };
+        Some(state.render_surface.color_space())
+    }
+
+    /// Change the presentation mode of the surface. Takes effect immediately if the renderer is active.
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        self.config.present_mode = present_mode;
+        if let RenderState::Active(state) = &mut self.render_state {
+            state.render_surface.set_present_mode(present_mode);
         }
-        let id = PAINT_SOURCE_ID.fetch_add(1, atomic::Ordering::SeqCst);
-        self.custom_paint_sources.insert(id, source);
+    }
 
-        id
+    /// Change the antialiasing method. Takes effect from the next frame.
+    pub fn set_antialiasing_method(&mut self, antialiasing_method: AaConfig) {
+        self.config.antialiasing_method = antialiasing_method;
     }
 
-    pub fn unregister_custom_paint_source(&mut self, id: u64) {
-        if let Some(mut source) = self.custom_paint_sources.remove(&id) {
-            source.suspend();
-            drop(source);
+    /// Recreates the device, surface and renderer after the device or surface has been lost
+    /// (e.g. due to a driver reset). Custom paint sources are suspended and resumed with the new device
+    /// if their device was lost.
+    fn recover(&mut self) {
+        let RenderState::Active(state) = &self.render_state else {
+            return;
+        };
+        let Some(window_handle) = self.window_handle.clone() else {
+            return;
+        };
+        let width = state.render_surface.config.width;
+        let height = state.render_surface.config.height;
+
+        self.suspend();
+        self.resume(window_handle, width, height);
+    }
+
+    /// Renders a frame, returning an error if the frame could not be rendered.
+    ///
+    /// Lost, outdated and timed out surfaces are handled by skipping the frame (recreating the surface
+    /// if it was lost). Other errors (e.g. running out of memory) are returned and the frame is skipped.
+    pub fn try_render<F: FnOnce(&mut <Self as WindowRenderer>::ScenePainter<'_>)>(
+        &mut self,
+        draw_fn: F,
+    ) -> Result<(), VelloRenderError> {
+        if self.current_device_handle().is_some_and(|device_handle| {
+            self.shared
+                .state
+                .borrow()
+                .wgpu_context
+                .is_device_lost(device_handle)
+        }) {
+            self.recover();
         }
+
+        let RenderState::Active(state) = &mut self.render_state else {
+            return Ok(());
+        };
+
+        let render_surface = &mut state.render_surface;
+
+        let mut shared = self.shared.state.borrow_mut();
+        let Some((renderer, custom_paint_sources)) =
+            shared.renderer_mut(&render_surface.device_handle)
+        else {
+            return Ok(());
+        };
+
+        debug_timer!(timer, feature = "log_frame_times");
+
+        // Regenerate the vello scene
+        draw_fn(&mut VelloScenePainter {
+            inner: &mut self.scene,
+            renderer: Some(&mut *renderer),
+            custom_paint_sources,
+        });
+        timer.record_time("cmd");
+
+        let render_result = renderer.render_to_texture(
+            render_surface.device(),
+            render_surface.queue(),
+            &self.scene,
+            &render_surface.target_texture_view(),
+            &RenderParams {
+                base_color: self.config.base_color,
+                width: render_surface.config.width,
+                height: render_surface.config.height,
+                antialiasing_method: self.config.antialiasing_method,
+            },
+        );
+        if let Err(err) = render_result {
+            self.scene.reset();
+            return Err(err.into());
+        }
+        timer.record_time("render");
+
+        let surface_lost = match render_surface.try_maybe_blit_and_present() {
+            Ok(()) => false,
+            Err(SurfaceError::Lost) => true,
+            // Skip presenting this frame
+            Err(SurfaceError::Outdated | SurfaceError::Timeout) => false,
+            Err(err) => {
+                self.scene.reset();
+                return Err(err.into());
+            }
+        };
+        timer.record_time("present");
+
+        let wait_result = state.wait_for_gpu(
+            self.config.frame_pacing,
+            self.config.desired_maximum_frame_latency,
+        );
+        timer.record_time("wait");
+        timer.print_times("vello: ");
+
+        // static COUNTER: AtomicU64 = AtomicU64::new(0);
+        // println!("FRAME {}", COUNTER.fetch_add(1, atomic::Ordering::Relaxed));
+
+        // Empty the Vello scene (memory optimisation)
+        self.scene.reset();
+        drop(shared);
+
+        // Recreate the surface so that the next frame can be presented
+        if surface_lost {
+            self.recover();
+        }
+
+        wait_result.map_err(VelloRenderError::from)
     }
 }
 
@@ -128,17 +330,23 @@ impl WindowRenderer for VelloWindowRenderer {
     }
 
     fn resume(&mut self, window_handle: Arc<dyn WindowHandle>, width: u32, height: u32) {
+        let mut shared = self.shared.state.borrow_mut();
+
         // Create wgpu_context::SurfaceRenderer
-        let render_surface = pollster::block_on(self.wgpu_context.create_surface(
+        let surface_formats = self
+            .config
+            .output_color_space
+            .surface_formats(&self.config.surface_formats);
+        let render_surface = pollster::block_on(shared.wgpu_context.create_surface(
             window_handle.clone(),
             SurfaceRendererConfiguration {
                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                formats: vec![TextureFormat::Rgba8Unorm, TextureFormat::Bgra8Unorm],
+                formats: surface_formats,
                 width,
                 height,
-                present_mode: PresentMode::AutoVsync,
-                desired_maximum_frame_latency: 2,
-                alpha_mode: wgpu::CompositeAlphaMode::Auto,
+                present_mode: self.config.present_mode,
+                desired_maximum_frame_latency: self.config.desired_maximum_frame_latency,
+                alpha_mode: self.config.alpha_mode,
                 view_formats: vec![],
             },
             Some(TextureConfiguration {
@@ -147,37 +355,25 @@ impl WindowRenderer for VelloWindowRenderer {
         ))
         .expect("Error creating surface");
 
-        // Create vello::Renderer
-        let renderer = VelloRenderer::new(
-            render_surface.device(),
-            RendererOptions {
-                antialiasing_support: AaSupport::all(),
-                use_cpu: false,
-                num_init_threads: DEFAULT_THREADS,
-                // TODO: add pipeline cache
-                pipeline_cache: None,
-            },
-        )
-        .unwrap();
-
-        // Resume custom paint sources
-        let device_handle = &render_surface.device_handle;
-        for source in self.custom_paint_sources.values_mut() {
-            source.resume(device_handle)
-        }
+        // Create (or reuse) the vello::Renderer for the device, and resume custom paint sources
+        shared.acquire_renderer(&render_surface.device_handle);
+        drop(shared);
 
         // Set state to Active
         self.window_handle = Some(window_handle);
         self.render_state = RenderState::Active(ActiveRenderState {
-            renderer,
             render_surface,
+            frames_in_flight: VecDeque::new(),
         });
     }
 
     fn suspend(&mut self) {
-        // Suspend custom paint sources
-        for source in self.custom_paint_sources.values_mut() {
-            source.suspend()
+        // Release the vello::Renderer (suspending custom paint sources if this was the last active window)
+        if let RenderState::Active(state) = &self.render_state {
+            self.shared
+                .state
+                .borrow_mut()
+                .release_renderer(&state.render_surface.device_handle);
         }
 
         // Set state to Suspended
@@ -191,51 +387,19 @@ impl WindowRenderer for VelloWindowRenderer {
     }
 
     fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F) {
-        let RenderState::Active(state) = &mut self.render_state else {
-            return;
-        };
-
-        let render_surface = &state.render_surface;
-
-        debug_timer!(timer, feature = "log_frame_times");
-
-        // Regenerate the vello scene
-        draw_fn(&mut VelloScenePainter {
-            inner: &mut self.scene,
-            renderer: Some(&mut state.renderer),
-            custom_paint_sources: Some(&mut self.custom_paint_sources),
-        });
-        timer.record_time("cmd");
-
-        state
-            .renderer
-            .render_to_texture(
-                render_surface.device(),
-                render_surface.queue(),
-                &self.scene,
-                &render_surface.target_texture_view(),
-                &RenderParams {
-                    base_color: self.config.base_color,
-                    width: render_surface.config.width,
-                    height: render_surface.config.height,
-                    antialiasing_method: self.config.antialiasing_method,
-                },
-            )
-            .expect("failed to render to texture");
-        timer.record_time("render");
-
-        render_surface.maybe_blit_and_present();
-        timer.record_time("present");
-
-        render_surface.device().poll(wgpu::PollType::Wait).unwrap();
-
-        timer.record_time("wait");
-        timer.print_times("vello: ");
+        if let Err(err) = self.try_render(draw_fn) {
+            eprintln!("WARNING: failed to render frame: {err}");
+        }
+    }
 
-        // static COUNTER: AtomicU64 = AtomicU64::new(0);
-        // println!("FRAME {}", COUNTER.fetch_add(1, atomic::Ordering::Relaxed));
+    fn set_base_color(&mut self, color: Color) {
+        self.config.base_color = color;
+    }
+}
 
-        // Empty the Vello scene (memory optimisation)
-        self.scene.reset();
+impl Drop for VelloWindowRenderer {
+    fn drop(&mut self) {
+        // Release this window's reference to the shared vello::Renderer
+        self.suspend();
     }
 }
@@ -1,10 +1,14 @@
 use crate::VelloCpuScenePainter;
-use anyrender::ImageRenderer;
+use anyrender::{ImageRenderer, PaintScene};
 use debug_timer::debug_timer;
+use kurbo::{Affine, Rect};
+use peniko::{Color, Fill};
 use vello_cpu::{RenderContext, RenderMode};
 
 pub struct VelloCpuImageRenderer {
     scene: VelloCpuScenePainter,
+    /// The color that the image is filled with before drawing the scene
+    base_color: Color,
 }
 
 impl ImageRenderer for VelloCpuImageRenderer {
@@ -13,6 +17,7 @@ impl ImageRenderer for VelloCpuImageRenderer {
     fn new(width: u32, height: u32) -> Self {
         Self {
             scene: VelloCpuScenePainter(RenderContext::new(width as u16, height as u16)),
+            base_color: Color::TRANSPARENT,
         }
     }
 
@@ -27,6 +32,17 @@ impl ImageRenderer for VelloCpuImageRenderer {
     fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F, buffer: &mut [u8]) {
         debug_timer!(timer, feature = "log_frame_times");
 
+        if self.base_color.components[3] != 0.0 {
+            let width = self.scene.0.width() as f64;
+            let height = self.scene.0.height() as f64;
+            self.scene.fill(
+                Fill::NonZero,
+                Affine::IDENTITY,
+                self.base_color,
+                None,
+                &Rect::new(0.0, 0.0, width, height),
+            );
+        }
         draw_fn(&mut self.scene);
         timer.record_time("cmds");
 
@@ -54,4 +70,8 @@ impl ImageRenderer for VelloCpuImageRenderer {
         buffer.resize(width as usize * height as usize * 4, 0);
         self.render(draw_fn, &mut *buffer);
     }
+
+    fn set_base_color(&mut self, color: Color) {
+        self.base_color = color;
+    }
 }
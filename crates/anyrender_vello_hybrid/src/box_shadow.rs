@@ -0,0 +1,246 @@
+//! Blurred rounded rectangles (box shadows), which vello_hybrid doesn't support natively.
+//!
+//! Shadows are rendered on the CPU into an image containing the blurred coverage of the rounded rectangle
+//! (multiplied by the shadow color), which is then uploaded and drawn as an image brush. Rendered shadows
+//! are cached so that static shadows are only rendered and uploaded once. The cache key is quantized to
+//! image pixels (and the scale to [`SCALE_STEP`]s), so that animated or zoomed shadows only need to be
+//! re-rendered when they change visibly.
+
+use kurbo::{Rect, Size};
+use peniko::{Blob, Color, ImageAlphaType, ImageData, ImageFormat};
+use std::sync::Arc;
+
+/// The number of standard deviations beyond the edge of the rectangle that the shadow extends.
+/// The gaussian is effectively zero beyond this.
+const BLUR_EXTENT: f64 = 3.0;
+
+/// The maximum width or height of a shadow image. Larger shadows are rendered at a reduced resolution.
+const MAX_IMAGE_DIMENSION: f64 = 2048.0;
+
+/// Shadow scales (image pixels per unit) above this are rounded down to a multiple of it. Smaller scales
+/// are rounded down to a power of two.
+const SCALE_STEP: f64 = 1.0 / 16.0;
+
+/// The corner radius and blur of a shadow are rounded to this many steps per image pixel
+const SUBPIXEL_STEPS: f64 = 4.0;
+
+/// Identifies a rendered box shadow image, in image pixels. Shadows which round to the same size, radius,
+/// blur and color (regardless of position and scale) are rendered identically, so they share an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct BoxShadowKey {
+    /// The size of the rectangle in image pixels
+    width: u32,
+    height: u32,
+    /// The corner radius and the standard deviation of the blur in 1/[`SUBPIXEL_STEPS`] image pixels
+    radius: u32,
+    std_dev: u32,
+    color: [u8; 4],
+}
+
+impl BoxShadowKey {
+    /// The key for the shadow of a rectangle of `size` which is rendered at `scale` image pixels per unit
+    /// (see [`shadow_scale`])
+    pub(crate) fn new(size: Size, radius: f64, std_dev: f64, scale: f64, color: Color) -> Self {
+        let subpixels = |value: f64| (value.max(0.0) * scale * SUBPIXEL_STEPS).round() as u32;
+        Self {
+            width: (size.width * scale).round().max(1.0) as u32,
+            height: (size.height * scale).round().max(1.0) as u32,
+            radius: subpixels(radius),
+            std_dev: subpixels(std_dev).max(1),
+            color: color.to_rgba8().to_u8_array(),
+        }
+    }
+}
+
+/// The area covered by the shadow of `rect`, including the blur
+pub(crate) fn shadow_bounds(rect: Rect, std_dev: f64) -> Rect {
+    let margin = std_dev * BLUR_EXTENT;
+    rect.inflate(margin, margin)
+}
+
+/// The number of image pixels per unit of the shadow's coordinate space. This is the scale of the
+/// shadow's transform, limited so that the image doesn't exceed [`MAX_IMAGE_DIMENSION`] and rounded
+/// down (see [`SCALE_STEP`]) so that a shadow which is being zoomed is only occasionally re-rendered.
+pub(crate) fn shadow_scale(bounds: Rect, transform_scale: f64) -> f64 {
+    let max_side = bounds.width().max(bounds.height()).max(1.0);
+    let scale = transform_scale
+        .min(MAX_IMAGE_DIMENSION / max_side)
+        .max(1e-3);
+    if scale >= SCALE_STEP {
+        (scale / SCALE_STEP).floor() * SCALE_STEP
+    } else {
+        scale.log2().floor().exp2()
+    }
+}
+
+/// Render the shadow described by `key` into an image which covers [`shadow_bounds`] of the rectangle
+/// (in image pixels)
+pub(crate) fn render_box_shadow(key: &BoxShadowKey) -> ImageData {
+    let size = Size::new(key.width as f64, key.height as f64);
+    let radius = key.radius as f64 / SUBPIXEL_STEPS;
+    let std_dev = key.std_dev as f64 / SUBPIXEL_STEPS;
+
+    let bounds = shadow_bounds(Rect::from_origin_size((0.0, 0.0), size), std_dev);
+    let width = bounds.width().ceil() as u32;
+    let height = bounds.height().ceil() as u32;
+
+    let half_size = (size.width * 0.5, size.height * 0.5);
+    let corner = radius.min(half_size.0.min(half_size.1));
+    let [r, g, b, a] = key.color;
+
+    let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+    for py in 0..height {
+        // Position of the pixel center relative to the center of the rectangle
+        let y = bounds.y0 + py as f64 + 0.5 - half_size.1;
+        for px in 0..width {
+            let x = bounds.x0 + px as f64 + 0.5 - half_size.0;
+            let coverage = rounded_box_shadow(x, y, half_size, corner, std_dev);
+            data.extend_from_slice(&[r, g, b, (coverage * a as f64).round() as u8]);
+        }
+    }
+
+    ImageData {
+        data: Blob::new(Arc::new(data)),
+        format: ImageFormat::Rgba8,
+        alpha_type: ImageAlphaType::Alpha,
+        width,
+        height,
+    }
+}
+
+/// The coverage (0-1) of a rounded rectangle centered on the origin convolved with a gaussian.
+///
+/// The convolution is computed exactly in the x direction (using `erf`), and by sampling in the y direction.
+/// See <https://madebyevan.com/shaders/fast-rounded-rectangle-shadows/>.
+fn rounded_box_shadow(x: f64, y: f64, half_size: (f64, f64), corner: f64, sigma: f64) -> f64 {
+    const SAMPLES: usize = 4;
+
+    let low = y - half_size.1;
+    let high = y + half_size.1;
+    let start = (-BLUR_EXTENT * sigma).clamp(low, high);
+    let end = (BLUR_EXTENT * sigma).clamp(low, high);
+
+    let step = (end - start) / SAMPLES as f64;
+    let mut sample_y = start + step * 0.5;
+    let mut value = 0.0;
+    for _ in 0..SAMPLES {
+        value += rounded_box_shadow_x(x, y - sample_y, half_size, corner, sigma)
+            * gaussian(sample_y, sigma)
+            * step;
+        sample_y += step;
+    }
+    value.clamp(0.0, 1.0)
+}
+
+/// The coverage of a horizontal slice through the rounded rectangle, convolved with a gaussian in x
+fn rounded_box_shadow_x(x: f64, y: f64, half_size: (f64, f64), corner: f64, sigma: f64) -> f64 {
+    let delta = (half_size.1 - corner - y.abs()).min(0.0);
+    let curved = half_size.0 - corner + (corner * corner - delta * delta).max(0.0).sqrt();
+    let scale = std::f64::consts::FRAC_1_SQRT_2 / sigma;
+    let low = 0.5 + 0.5 * erf((x - curved) * scale);
+    let high = 0.5 + 0.5 * erf((x + curved) * scale);
+    high - low
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    const SQRT_2_PI: f64 = 2.5066282746310002;
+    (-(x * x) / (2.0 * sigma * sigma)).exp() / (SQRT_2_PI * sigma)
+}
+
+/// Approximation of the error function (maximum error ~5e-4)
+fn erf(x: f64) -> f64 {
+    let s = x.signum();
+    let a = x.abs();
+    let mut x = 1.0 + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+    x *= x;
+    s - s / (x * x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "expected {expected} (±{tolerance}), got {actual}"
+        );
+    }
+
+    #[test]
+    fn erf_matches_reference_values() {
+        assert_eq!(erf(0.0), 0.0);
+        assert_close(erf(0.5), 0.5204999, 5e-4);
+        assert_close(erf(1.0), 0.8427008, 5e-4);
+        assert_close(erf(2.0), 0.9953223, 5e-4);
+        assert_close(erf(10.0), 1.0, 1e-9);
+        for x in [0.1, 0.7, 1.5, 3.0] {
+            assert_eq!(erf(-x), -erf(x));
+        }
+    }
+
+    #[test]
+    fn rounded_box_shadow_coverage() {
+        // A 100x100 rectangle with a blur of 2
+        let coverage = |x, y, corner| rounded_box_shadow(x, y, (50.0, 50.0), corner, 2.0);
+        // Fully covered well inside the rectangle, uncovered well outside of it
+        assert_close(coverage(0.0, 0.0, 10.0), 1.0, 0.01);
+        assert_close(coverage(70.0, 0.0, 10.0), 0.0, 1e-6);
+        assert_close(coverage(0.0, -70.0, 10.0), 0.0, 1e-6);
+        // Half covered on a straight edge
+        assert_close(coverage(50.0, 0.0, 10.0), 0.5, 0.01);
+        assert_close(coverage(0.0, 50.0, 10.0), 0.5, 0.01);
+        // The rounded corner covers less than a square one
+        assert!(coverage(48.0, 48.0, 10.0) < coverage(48.0, 48.0, 0.0));
+    }
+
+    #[test]
+    fn shadow_bounds_include_blur() {
+        let bounds = shadow_bounds(Rect::new(0.0, 0.0, 10.0, 20.0), 2.0);
+        assert_eq!(bounds, Rect::new(-6.0, -6.0, 16.0, 26.0));
+    }
+
+    #[test]
+    fn shadow_scale_is_quantized() {
+        let bounds = Rect::new(0.0, 0.0, 100.0, 100.0);
+        assert_eq!(shadow_scale(bounds, 1.0), 1.0);
+        assert_eq!(shadow_scale(bounds, 1.03), 1.0);
+        assert_eq!(shadow_scale(bounds, 2.1), 2.0625);
+        // Small scales are rounded down to a power of two
+        assert_eq!(shadow_scale(bounds, 0.05), 0.03125);
+        assert_eq!(shadow_scale(bounds, 0.0), 2f64.powi(-10));
+        // Limited to the maximum image size
+        assert_eq!(shadow_scale(Rect::new(0.0, 0.0, 4096.0, 10.0), 1.0), 0.5);
+        assert_eq!(shadow_scale(Rect::new(0.0, 0.0, 3000.0, 10.0), 1.0), 0.625);
+    }
+
+    #[test]
+    fn key_is_quantized_to_image_pixels() {
+        let color = Color::BLACK;
+        let key = BoxShadowKey::new(Size::new(100.0, 50.0), 4.0, 2.0, 1.0, color);
+        assert_eq!(
+            key,
+            BoxShadowKey::new(Size::new(100.2, 49.9), 4.05, 2.01, 1.0, color)
+        );
+        assert_ne!(
+            key,
+            BoxShadowKey::new(Size::new(101.0, 50.0), 4.0, 2.0, 1.0, color)
+        );
+        assert_eq!(
+            key,
+            BoxShadowKey::new(Size::new(50.0, 25.0), 2.0, 1.0, 2.0, color)
+        );
+    }
+
+    #[test]
+    fn rendered_image_covers_bounds() {
+        let key = BoxShadowKey::new(Size::new(40.0, 60.0), 0.0, 2.0, 2.0, Color::WHITE);
+        let image = render_box_shadow(&key);
+        assert_eq!((image.width, image.height), (104, 144));
+        assert_eq!(image.data.len(), 104 * 144 * 4);
+
+        let alpha = |x: u32, y: u32| image.data.data()[((y * image.width + x) * 4 + 3) as usize];
+        assert_eq!(alpha(0, 0), 0);
+        assert_eq!(alpha(52, 72), 255);
+    }
+}
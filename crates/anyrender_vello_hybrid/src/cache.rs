@@ -0,0 +1,90 @@
+use rustc_hash::FxHashMap;
+use std::hash::Hash;
+
+struct CacheEntry<T> {
+    resource: T,
+    generation: usize,
+}
+
+/// A cache which evicts resources that haven't been used for `max_age` generations (frames)
+pub(crate) struct GenerationalCache<K, T>
+where
+    K: Eq + Hash + Clone,
+{
+    resources: FxHashMap<K, CacheEntry<T>>,
+    current_generation: usize,
+    max_age: usize,
+}
+
+impl<K, T> GenerationalCache<K, T>
+where
+    K: Eq + Hash + Clone,
+{
+    pub(crate) fn new(max_age: usize) -> Self {
+        GenerationalCache {
+            resources: FxHashMap::default(),
+            current_generation: 0,
+            max_age,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.resources.len()
+    }
+
+    pub(crate) fn set_max_age(&mut self, max_age: usize) {
+        self.max_age = max_age;
+    }
+
+    /// Advances to the next generation, evicting resources which have become too old
+    pub(crate) fn next_gen(&mut self, mut on_evict: impl FnMut(T)) {
+        let expired: Vec<K> = self
+            .resources
+            .iter()
+            .filter(|(_, entry)| {
+                self.current_generation.wrapping_sub(entry.generation) >= self.max_age
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            if let Some(entry) = self.resources.remove(&key) {
+                on_evict(entry.resource);
+            }
+        }
+
+        self.current_generation = self.current_generation.wrapping_add(1);
+    }
+
+    /// Evicts the least recently used resource, unless it has been used in the current generation
+    pub(crate) fn evict_oldest(&mut self) -> Option<T> {
+        let key = self
+            .resources
+            .iter()
+            .filter(|(_, entry)| entry.generation != self.current_generation)
+            .max_by_key(|(_, entry)| self.current_generation.wrapping_sub(entry.generation))
+            .map(|(key, _)| key.clone())?;
+        self.resources.remove(&key).map(|entry| entry.resource)
+    }
+
+    pub(crate) fn hit(&mut self, key: &K) -> Option<&T> {
+        if let Some(entry) = self.resources.get_mut(key) {
+            entry.generation = self.current_generation;
+            Some(&entry.resource)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: K, resource: T) {
+        let entry = CacheEntry {
+            resource,
+            generation: self.current_generation,
+        };
+        self.resources.insert(key, entry);
+    }
+
+    /// Removes all resources without evicting them individually
+    pub(crate) fn clear(&mut self) {
+        self.resources.clear();
+    }
+}
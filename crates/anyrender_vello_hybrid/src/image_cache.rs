@@ -0,0 +1,107 @@
+use vello_common::paint::ImageId;
+use vello_hybrid::Renderer;
+use wgpu::{CommandEncoder, Device, Queue};
+
+use crate::cache::GenerationalCache;
+
+/// Statistics about the images uploaded to a renderer's image atlas
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ImageCacheStats {
+    /// The number of images currently uploaded
+    pub images: usize,
+    /// The total size (in bytes) of the images currently uploaded
+    pub bytes: usize,
+    /// The total number of images that have been uploaded
+    pub uploads: u64,
+    /// The total number of images that have been evicted from the atlas
+    pub evictions: u64,
+}
+
+struct CachedImage {
+    image_id: ImageId,
+    bytes: usize,
+}
+
+/// Tracks the images uploaded to a `vello_hybrid::Renderer`, keyed by the id of the image's data.
+///
+/// Images which haven't been drawn for `max_age` frames are destroyed. If the uploaded images exceed the
+/// memory budget then the least recently drawn images are destroyed until they fit (images drawn in the
+/// current frame are never evicted, so the budget may be exceeded if a single frame uses more).
+pub(crate) struct ImageCache {
+    images: GenerationalCache<u64, CachedImage>,
+    budget: usize,
+    stats: ImageCacheStats,
+}
+
+impl ImageCache {
+    pub(crate) fn new(max_age: usize, budget: usize) -> Self {
+        Self {
+            images: GenerationalCache::new(max_age),
+            budget,
+            stats: ImageCacheStats::default(),
+        }
+    }
+
+    /// Changes the limits. Images exceeding the new limits are evicted at the end of the next frame.
+    pub(crate) fn set_limits(&mut self, max_age: usize, budget: usize) {
+        self.images.set_max_age(max_age);
+        self.budget = budget;
+    }
+
+    pub(crate) fn stats(&self) -> ImageCacheStats {
+        self.stats
+    }
+
+    pub(crate) fn get(&mut self, key: u64) -> Option<ImageId> {
+        self.images.hit(&key).map(|image| image.image_id)
+    }
+
+    pub(crate) fn insert(&mut self, key: u64, image_id: ImageId, bytes: usize) {
+        self.images.insert(key, CachedImage { image_id, bytes });
+        self.stats.images = self.images.len();
+        self.stats.bytes += bytes;
+        self.stats.uploads += 1;
+    }
+
+    /// Evicts images which are too old or over budget, and advances to the next frame.
+    /// Must be called after the frame's render commands have been encoded into `encoder`.
+    pub(crate) fn next_gen(
+        &mut self,
+        renderer: &mut Renderer,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+    ) {
+        while self.stats.bytes > self.budget {
+            let Some(image) = self.images.evict_oldest() else {
+                break;
+            };
+            destroy_image(&mut self.stats, renderer, device, queue, encoder, image);
+        }
+        self.images.next_gen(|image| {
+            destroy_image(&mut self.stats, renderer, device, queue, encoder, image)
+        });
+
+        self.stats.images = self.images.len();
+    }
+
+    /// Forgets all images. Used when the renderer (and therefore its atlas) has been dropped.
+    pub(crate) fn clear(&mut self) {
+        self.images.clear();
+        self.stats.images = 0;
+        self.stats.bytes = 0;
+    }
+}
+
+fn destroy_image(
+    stats: &mut ImageCacheStats,
+    renderer: &mut Renderer,
+    device: &Device,
+    queue: &Queue,
+    encoder: &mut CommandEncoder,
+    image: CachedImage,
+) {
+    renderer.destroy_image(device, queue, encoder, image.image_id);
+    stats.bytes -= image.bytes;
+    stats.evictions += 1;
+}
@@ -0,0 +1,225 @@
+use anyrender::ImageRenderer;
+use debug_timer::debug_timer;
+use peniko::{Color, ImageData};
+use vello_hybrid::{
+    RenderSettings, RenderSize, RenderTargetConfig, Renderer as VelloHybridRenderer,
+    Scene as VelloHybridScene,
+};
+use wgpu::{CommandEncoderDescriptor, TextureFormat, TextureUsages};
+use wgpu_context::{BufferRenderer, BufferRendererConfig, DeviceHandle, WGPUContext};
+
+use crate::{
+    ImageCacheStats, VelloHybridScenePainter, box_shadow::BoxShadowKey, cache::GenerationalCache,
+    image_cache::ImageCache, scene::ImageManager,
+};
+
+#[derive(Clone)]
+pub struct VelloHybridImageRendererOptions {
+    pub render_settings: RenderSettings,
+    /// The color that the image is filled with before drawing the scene
+    pub base_color: Color,
+    /// The number of renders that an image can go without being drawn before it is evicted from the image atlas
+    pub image_cache_max_age: usize,
+    /// The maximum total size (in bytes) of the images kept in the image atlas
+    pub image_cache_budget: usize,
+}
+
+impl Default for VelloHybridImageRendererOptions {
+    fn default() -> Self {
+        Self {
+            render_settings: RenderSettings::default(),
+            base_color: Color::TRANSPARENT,
+            image_cache_max_age: 60,
+            image_cache_budget: 256 * 1024 * 1024,
+        }
+    }
+}
+
+pub struct VelloHybridImageRenderer {
+    buffer_renderer: BufferRenderer,
+    renderer: VelloHybridRenderer,
+    scene: VelloHybridScene,
+    config: VelloHybridImageRendererOptions,
+    cached_images: ImageCache,
+    cached_box_shadows: GenerationalCache<BoxShadowKey, ImageData>,
+}
+
+impl VelloHybridImageRenderer {
+    pub fn with_options(width: u32, height: u32, config: VelloHybridImageRendererOptions) -> Self {
+        Self::with_wgpu_context(WGPUContext::new(), width, height, config)
+    }
+
+    /// Create a renderer which renders using an existing device rather than creating its own
+    pub fn with_device_handle(
+        device_handle: DeviceHandle,
+        width: u32,
+        height: u32,
+        config: VelloHybridImageRendererOptions,
+    ) -> Self {
+        Self::with_wgpu_context(
+            WGPUContext::with_device_handle(device_handle),
+            width,
+            height,
+            config,
+        )
+    }
+
+    fn with_wgpu_context(
+        mut wgpu_context: WGPUContext,
+        width: u32,
+        height: u32,
+        config: VelloHybridImageRendererOptions,
+    ) -> Self {
+        // vello_hybrid renders using a render pass (rather than compute shaders)
+        let buffer_renderer =
+            pollster::block_on(wgpu_context.create_buffer_renderer(BufferRendererConfig {
+                width,
+                height,
+                usage: TextureUsages::RENDER_ATTACHMENT,
+            }))
+            .expect("No compatible device found");
+
+        let renderer = VelloHybridRenderer::new(
+            buffer_renderer.device(),
+            &RenderTargetConfig {
+                format: TextureFormat::Rgba8Unorm,
+                width,
+                height,
+            },
+        );
+
+        Self {
+            buffer_renderer,
+            renderer,
+            scene: VelloHybridScene::new_with(width as u16, height as u16, config.render_settings),
+            cached_images: ImageCache::new(config.image_cache_max_age, config.image_cache_budget),
+            cached_box_shadows: GenerationalCache::new(config.image_cache_max_age),
+            config,
+        }
+    }
+
+    /// Change the settings used to build the scene. Takes effect from the next render.
+    pub fn set_render_settings(&mut self, render_settings: RenderSettings) {
+        self.config.render_settings = render_settings;
+        self.scene =
+            VelloHybridScene::new_with(self.scene.width(), self.scene.height(), render_settings);
+    }
+
+    /// Change the image atlas eviction limits. Images exceeding the new limits are evicted at the end of the next render.
+    pub fn set_image_cache_limits(&mut self, max_age: usize, budget: usize) {
+        self.config.image_cache_max_age = max_age;
+        self.config.image_cache_budget = budget;
+        self.cached_images.set_limits(max_age, budget);
+        self.cached_box_shadows.set_max_age(max_age);
+    }
+
+    /// Statistics about the images currently uploaded to the renderer's image atlas
+    pub fn image_cache_stats(&self) -> ImageCacheStats {
+        self.cached_images.stats()
+    }
+}
+
+impl ImageRenderer for VelloHybridImageRenderer {
+    type ScenePainter<'a>
+        = VelloHybridScenePainter<'a>
+    where
+        Self: 'a;
+
+    fn new(width: u32, height: u32) -> Self {
+        Self::with_options(width, height, VelloHybridImageRendererOptions::default())
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.buffer_renderer.resize(width, height);
+        self.scene =
+            VelloHybridScene::new_with(width as u16, height as u16, self.config.render_settings);
+    }
+
+    fn reset(&mut self) {
+        self.scene.reset();
+    }
+
+    fn render_to_vec<F: FnOnce(&mut Self::ScenePainter<'_>)>(
+        &mut self,
+        draw_fn: F,
+        buffer: &mut Vec<u8>,
+    ) {
+        let size = self.buffer_renderer.size();
+        buffer.resize(size.width as usize * size.height as usize * 4, 0);
+        self.render(draw_fn, &mut *buffer);
+    }
+
+    fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F, buffer: &mut [u8]) {
+        debug_timer!(timer, feature = "log_frame_times");
+
+        let buffer_renderer = &mut self.buffer_renderer;
+        let size = buffer_renderer.size();
+        let mut encoder =
+            buffer_renderer
+                .device()
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Render scene"),
+                });
+
+        let image_manager = ImageManager {
+            renderer: &mut self.renderer,
+            device: buffer_renderer.device(),
+            queue: buffer_renderer.queue(),
+            encoder: &mut encoder,
+            cache: &mut self.cached_images,
+            box_shadow_cache: &mut self.cached_box_shadows,
+        };
+
+        // Regenerate the vello scene
+        let mut painter = VelloHybridScenePainter {
+            scene: &mut self.scene,
+            image_manager: Some(image_manager),
+        };
+        painter.fill_base_color(self.config.base_color, size.width, size.height);
+        draw_fn(&mut painter);
+        timer.record_time("cmd");
+
+        // The texture may be larger than the renderer's size (only the top-left region is copied out).
+        // Rendering at the texture's size keeps the scene's pixels mapped 1:1 onto that region.
+        let texture_view = buffer_renderer.target_texture_view();
+        let texture_size = texture_view.texture().size();
+        self.renderer
+            .render(
+                &self.scene,
+                buffer_renderer.device(),
+                buffer_renderer.queue(),
+                &mut encoder,
+                &RenderSize {
+                    width: texture_size.width,
+                    height: texture_size.height,
+                },
+                &texture_view,
+            )
+            .expect("failed to render to texture");
+
+        // Evict images which are no longer being drawn
+        self.cached_images.next_gen(
+            &mut self.renderer,
+            buffer_renderer.device(),
+            buffer_renderer.queue(),
+            &mut encoder,
+        );
+        self.cached_box_shadows.next_gen(drop);
+
+        buffer_renderer.queue().submit([encoder.finish()]);
+        timer.record_time("render");
+
+        buffer_renderer.copy_texture_to_buffer(buffer);
+        buffer_renderer.end_frame();
+        timer.record_time("read");
+
+        timer.print_times("vello_hybrid_image: ");
+
+        // Empty the Vello scene (memory optimisation)
+        self.scene.reset();
+    }
+
+    fn set_base_color(&mut self, color: Color) {
+        self.config.base_color = color;
+    }
+}
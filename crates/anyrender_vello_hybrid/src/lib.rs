@@ -1,8 +1,14 @@
 //! A [`vello_hybrid`] backend for the [`anyrender`] 2D drawing abstraction
 #![cfg_attr(docsrs, feature(doc_cfg))]
 
+mod box_shadow;
+mod cache;
+mod image_cache;
+mod image_renderer;
 mod scene;
 mod window_renderer;
 
+pub use image_cache::ImageCacheStats;
+pub use image_renderer::{VelloHybridImageRenderer, VelloHybridImageRendererOptions};
 pub use scene::VelloHybridScenePainter;
 pub use window_renderer::*;
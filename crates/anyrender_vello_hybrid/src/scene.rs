@@ -1,11 +1,16 @@
 use anyrender::{NormalizedCoord, Paint, PaintRef, PaintScene};
-use kurbo::{Affine, Rect, Shape, Stroke};
-use peniko::{BlendMode, Color, Fill, FontData, ImageBrush, ImageData, StyleRef};
-use rustc_hash::FxHashMap;
+use kurbo::{Affine, Rect, RoundedRect, Shape, Stroke};
+use peniko::{BlendMode, Color, Fill, FontData, ImageBrush, ImageData, ImageSampler, StyleRef};
 use vello_common::paint::{ImageId, ImageSource, PaintType};
 use vello_hybrid::Renderer;
 use wgpu::{CommandEncoder, Device, Queue};
 
+use crate::{
+    box_shadow::{self, BoxShadowKey},
+    cache::GenerationalCache,
+    image_cache::ImageCache,
+};
+
 const DEFAULT_TOLERANCE: f64 = 0.1;
 
 fn anyrender_paint_to_vello_hybrid_paint<'a>(
@@ -38,7 +43,8 @@ pub(crate) struct ImageManager<'a> {
     pub(crate) device: &'a Device,
     pub(crate) queue: &'a Queue,
     pub(crate) encoder: &'a mut CommandEncoder,
-    pub(crate) cache: &'a mut FxHashMap<u64, ImageId>,
+    pub(crate) cache: &'a mut ImageCache,
+    pub(crate) box_shadow_cache: &'a mut GenerationalCache<BoxShadowKey, ImageData>,
 }
 
 impl ImageManager<'_> {
@@ -46,8 +52,8 @@ impl ImageManager<'_> {
         let peniko_id = image.data.id();
 
         // Try to get ImageId from cache first
-        if let Some(atlas_id) = self.cache.get(&peniko_id) {
-            return *atlas_id;
+        if let Some(atlas_id) = self.cache.get(peniko_id) {
+            return atlas_id;
         };
 
         // Convert ImageData to Pixmap
@@ -61,11 +67,23 @@ impl ImageManager<'_> {
             .upload_image(self.device, self.queue, self.encoder, &pixmap);
 
         // Store ImageId in cache
-        self.cache.insert(peniko_id, atlas_id);
+        let bytes = pixmap.width() as usize * pixmap.height() as usize * 4;
+        self.cache.insert(peniko_id, atlas_id, bytes);
 
         // Return ImageId
         atlas_id
     }
+
+    /// Get the image for a box shadow from the cache, rendering it if necessary
+    pub(crate) fn box_shadow_image(&mut self, key: BoxShadowKey) -> ImageData {
+        if let Some(image) = self.box_shadow_cache.hit(&key) {
+            return image.clone();
+        }
+
+        let image = box_shadow::render_box_shadow(&key);
+        self.box_shadow_cache.insert(key, image.clone());
+        image
+    }
 }
 
 pub struct VelloHybridScenePainter<'s> {
@@ -80,6 +98,14 @@ impl VelloHybridScenePainter<'_> {
             image_manager: None,
         }
     }
+
+    /// Fill the whole `width`x`height` area with `color` (if it isn't fully transparent)
+    pub(crate) fn fill_base_color(&mut self, color: Color, width: u32, height: u32) {
+        if color.components[3] != 0.0 {
+            let rect = Rect::new(0.0, 0.0, width as f64, height as f64);
+            self.fill(Fill::NonZero, Affine::IDENTITY, color, None, &rect);
+        }
+    }
 }
 
 impl PaintScene for VelloHybridScenePainter<'_> {
@@ -201,17 +227,42 @@ impl PaintScene for VelloHybridScenePainter<'_> {
     }
     fn draw_box_shadow(
         &mut self,
-        _transform: Affine,
-        _rect: Rect,
-        _color: Color,
-        _radius: f64,
-        _std_dev: f64,
+        transform: Affine,
+        rect: Rect,
+        color: Color,
+        radius: f64,
+        std_dev: f64,
     ) {
-        // FIXME: implement once supported in vello_hybrid
-        //
-        // self.scene.set_transform(transform);
-        // self.scene.set_paint(PaintType::Solid(color));
-        // self.scene
-        //     .fill_blurred_rounded_rect(&rect, radius as f32, std_dev as f32);
+        // vello_hybrid doesn't support blurred rounded rects, so the shadow is rendered into an image.
+        // Without an image manager (or blur) the unblurred rounded rect is drawn instead.
+        let rect = rect.abs();
+        let Some(image_manager) = self.image_manager.as_mut().filter(|_| std_dev > 0.0) else {
+            let shape = RoundedRect::from_rect(rect, radius);
+            self.fill(Fill::NonZero, transform, color, None, &shape);
+            return;
+        };
+
+        let bounds = box_shadow::shadow_bounds(rect, std_dev);
+        let scale = box_shadow::shadow_scale(bounds, transform.determinant().abs().sqrt());
+        let key = BoxShadowKey::new(rect.size(), radius, std_dev, scale, color);
+        let image = image_manager.box_shadow_image(key);
+
+        // The image is stretched over the bounds, as the key is rounded to whole image pixels
+        let brush_transform = Affine::translate(bounds.origin().to_vec2())
+            * Affine::scale_non_uniform(
+                bounds.width() / image.width as f64,
+                bounds.height() / image.height as f64,
+            );
+        let brush = ImageBrush {
+            image: &image,
+            sampler: ImageSampler::default(),
+        };
+        self.fill(
+            Fill::NonZero,
+            transform,
+            brush,
+            Some(brush_transform),
+            &bounds,
+        );
     }
 }
@@ -1,21 +1,26 @@
 use anyrender::{WindowHandle, WindowRenderer};
 use debug_timer::debug_timer;
-use rustc_hash::FxHashMap;
+use peniko::{Color, ImageData};
 use std::sync::{
     Arc,
     // atomic::{AtomicU64},
 };
-use vello_common::paint::ImageId;
 use vello_hybrid::{
     RenderSettings, RenderSize, RenderTargetConfig, Renderer as VelloHybridRenderer,
     Scene as VelloHybridScene,
 };
 use wgpu::{
-    CommandEncoderDescriptor, Features, Limits, PresentMode, TextureFormat, TextureViewDescriptor,
+    CommandEncoderDescriptor, CompositeAlphaMode, Features, Limits, PresentMode, SurfaceError,
+    TextureFormat, TextureViewDescriptor,
+};
+use wgpu_context::{
+    AdapterSelection, DeviceHandle, SurfaceRenderer, SurfaceRendererConfiguration, WGPUContext,
 };
-use wgpu_context::{DeviceHandle, SurfaceRenderer, SurfaceRendererConfiguration, WGPUContext};
 
-use crate::{VelloHybridScenePainter, scene::ImageManager};
+use crate::{
+    ImageCacheStats, VelloHybridScenePainter, box_shadow::BoxShadowKey, cache::GenerationalCache,
+    image_cache::ImageCache, scene::ImageManager,
+};
 // use crate::CustomPaintSource;
 
 // static PAINT_SOURCE_ID: AtomicU64 = AtomicU64::new(0);
@@ -41,11 +46,56 @@ impl RenderState {
     }
 }
 
-#[derive(Clone, Default)]
+/// The surface formats that vello_hybrid can render to. Its output is already sRGB-encoded, so it can't be
+/// written to `*Srgb` formats (which would encode it again) or to extended range formats.
+pub const SUPPORTED_SURFACE_FORMATS: [TextureFormat; 2] =
+    [TextureFormat::Rgba8Unorm, TextureFormat::Bgra8Unorm];
+
+#[derive(Clone)]
 pub struct VelloHybridRendererOptions {
     pub features: Option<Features>,
     pub limits: Option<Limits>,
     pub render_settings: RenderSettings,
+    /// The color that the window is filled with before drawing the scene. Can be changed at runtime
+    /// using [`WindowRenderer::set_base_color`]
+    pub base_color: Color,
+    /// Presentation mode of the surface. Can be changed at runtime using [`VelloHybridWindowRenderer::set_present_mode`]
+    pub present_mode: PresentMode,
+    /// Desired maximum number of frames that the presentation engine should queue in advance
+    pub desired_maximum_frame_latency: u32,
+    /// How the alpha channel of the surface should be handled during compositing
+    pub alpha_mode: CompositeAlphaMode,
+    /// The surface formats that may be used. The format that the surface prefers most will be used.
+    ///
+    /// vello_hybrid renders sRGB-encoded values directly to the surface, so only 8-bit non-sRGB formats
+    /// ([`SUPPORTED_SURFACE_FORMATS`]) are supported. Other formats are ignored, and if none are left then
+    /// the default formats are used.
+    pub surface_formats: Vec<TextureFormat>,
+    /// Policy for choosing which GPU to render with
+    pub adapter_selection: AdapterSelection,
+    /// The number of frames that an image can go without being drawn before it is evicted from the image atlas
+    pub image_cache_max_age: usize,
+    /// The maximum total size (in bytes) of the images kept in the image atlas. The least recently drawn
+    /// images are evicted when this is exceeded.
+    pub image_cache_budget: usize,
+}
+
+impl Default for VelloHybridRendererOptions {
+    fn default() -> Self {
+        Self {
+            features: None,
+            limits: None,
+            render_settings: RenderSettings::default(),
+            base_color: Color::TRANSPARENT,
+            present_mode: PresentMode::AutoVsync,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: CompositeAlphaMode::Auto,
+            surface_formats: SUPPORTED_SURFACE_FORMATS.to_vec(),
+            adapter_selection: AdapterSelection::default(),
+            image_cache_max_age: 60,
+            image_cache_budget: 256 * 1024 * 1024,
+        }
+    }
 }
 
 pub struct VelloHybridWindowRenderer {
@@ -59,7 +109,8 @@ pub struct VelloHybridWindowRenderer {
     scene: VelloHybridScene,
     config: VelloHybridRendererOptions,
     // custom_paint_sources: FxHashMap<u64, Box<dyn CustomPaintSource>>,
-    cached_images: FxHashMap<u64, ImageId>,
+    cached_images: ImageCache,
+    cached_box_shadows: GenerationalCache<BoxShadowKey, ImageData>,
 }
 impl VelloHybridWindowRenderer {
     #[allow(clippy::new_without_default)]
@@ -71,18 +122,35 @@ impl VelloHybridWindowRenderer {
         let features = config.features.unwrap_or_default()
             | Features::CLEAR_TEXTURE
             | Features::PIPELINE_CACHE;
+        let mut wgpu_context =
+            WGPUContext::with_features_and_limits(Some(features), config.limits.clone());
+        wgpu_context.set_adapter_selection(config.adapter_selection.clone());
+        Self::with_wgpu_context(wgpu_context, config)
+    }
+
+    /// Create a renderer which renders using an existing device rather than creating its own.
+    ///
+    /// `features` and `limits` in `config` are ignored as the device has already been created.
+    pub fn with_device_handle(
+        device_handle: DeviceHandle,
+        config: VelloHybridRendererOptions,
+    ) -> Self {
+        Self::with_wgpu_context(WGPUContext::with_device_handle(device_handle), config)
+    }
+
+    fn with_wgpu_context(wgpu_context: WGPUContext, config: VelloHybridRendererOptions) -> Self {
         let render_settings = config.render_settings;
+        let cached_images = ImageCache::new(config.image_cache_max_age, config.image_cache_budget);
+        let cached_box_shadows = GenerationalCache::new(config.image_cache_max_age);
         Self {
-            wgpu_context: WGPUContext::with_features_and_limits(
-                Some(features),
-                config.limits.clone(),
-            ),
+            wgpu_context,
             config,
             render_state: RenderState::Suspended,
             window_handle: None,
             scene: VelloHybridScene::new_with(0, 0, render_settings),
             // custom_paint_sources: FxHashMap::default(),
-            cached_images: FxHashMap::default(),
+            cached_images,
+            cached_box_shadows,
         }
     }
 
@@ -90,6 +158,73 @@ impl VelloHybridWindowRenderer {
         self.render_state.current_device_handle()
     }
 
+    /// Change the presentation mode of the surface. Takes effect immediately if the renderer is active.
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+        self.config.present_mode = present_mode;
+        if let RenderState::Active(state) = &mut self.render_state {
+            state.render_surface.set_present_mode(present_mode);
+        }
+    }
+
+    /// Change the settings used to build the scene. Takes effect from the next frame.
+    pub fn set_render_settings(&mut self, render_settings: RenderSettings) {
+        self.config.render_settings = render_settings;
+        self.scene =
+            VelloHybridScene::new_with(self.scene.width(), self.scene.height(), render_settings);
+    }
+
+    /// Change the surface formats (see [`VelloHybridRendererOptions::surface_formats`]). If the renderer
+    /// is active then the surface is recreated.
+    pub fn set_surface_formats(&mut self, surface_formats: Vec<TextureFormat>) {
+        self.config.surface_formats = surface_formats;
+        self.recover();
+    }
+
+    /// The configured surface formats which are supported, or all supported formats if there are none
+    fn surface_formats(&self) -> Vec<TextureFormat> {
+        let formats: Vec<TextureFormat> = self
+            .config
+            .surface_formats
+            .iter()
+            .copied()
+            .filter(|format| SUPPORTED_SURFACE_FORMATS.contains(format))
+            .collect();
+        if formats.is_empty() {
+            SUPPORTED_SURFACE_FORMATS.to_vec()
+        } else {
+            formats
+        }
+    }
+
+    /// Change the image atlas eviction limits. Images exceeding the new limits are evicted at the end of the next frame.
+    pub fn set_image_cache_limits(&mut self, max_age: usize, budget: usize) {
+        self.config.image_cache_max_age = max_age;
+        self.config.image_cache_budget = budget;
+        self.cached_images.set_limits(max_age, budget);
+        self.cached_box_shadows.set_max_age(max_age);
+    }
+
+    /// Statistics about the images currently uploaded to the renderer's image atlas
+    pub fn image_cache_stats(&self) -> ImageCacheStats {
+        self.cached_images.stats()
+    }
+
+    /// Recreates the device, surface and renderer after the device or surface has been lost
+    /// (e.g. due to a driver reset).
+    fn recover(&mut self) {
+        let RenderState::Active(state) = &self.render_state else {
+            return;
+        };
+        let Some(window_handle) = self.window_handle.clone() else {
+            return;
+        };
+        let width = state.render_surface.config.width;
+        let height = state.render_surface.config.height;
+
+        self.suspend();
+        self.resume(window_handle, width, height);
+    }
+
     // pub fn register_custom_paint_source(&mut self, mut source: Box<dyn CustomPaintSource>) -> u64 {
     //     if let Some(device_handle) = self.render_state.current_device_handle() {
     //         source.resume(device_handle);
@@ -106,6 +241,122 @@ impl VelloHybridWindowRenderer {
     //         drop(source);
     //     }
     // }
+
+    /// Renders a frame, returning an error if the surface could not be rendered to.
+    ///
+    /// Lost, outdated and timed out surfaces are handled by skipping the frame (recreating the surface
+    /// if it was lost). Other errors (e.g. running out of memory) are returned and the frame is skipped.
+    pub fn try_render<F: FnOnce(&mut <Self as WindowRenderer>::ScenePainter<'_>)>(
+        &mut self,
+        draw_fn: F,
+    ) -> Result<(), SurfaceError> {
+        if self
+            .current_device_handle()
+            .is_some_and(|device_handle| self.wgpu_context.is_device_lost(device_handle))
+        {
+            self.recover();
+        }
+
+        let RenderState::Active(state) = &mut self.render_state else {
+            return Ok(());
+        };
+
+        let render_surface = &state.render_surface;
+
+        debug_timer!(timer, feature = "log_frame_times");
+
+        let mut encoder =
+            render_surface
+                .device()
+                .create_command_encoder(&CommandEncoderDescriptor {
+                    label: Some("Render scene"),
+                });
+
+        let image_manager = ImageManager {
+            renderer: &mut state.renderer,
+            device: render_surface.device(),
+            queue: render_surface.queue(),
+            encoder: &mut encoder,
+            cache: &mut self.cached_images,
+            box_shadow_cache: &mut self.cached_box_shadows,
+        };
+
+        // Regenerate the vello scene
+        let mut painter = VelloHybridScenePainter {
+            scene: &mut self.scene,
+            image_manager: Some(image_manager),
+        };
+        painter.fill_base_color(
+            self.config.base_color,
+            render_surface.config.width,
+            render_surface.config.height,
+        );
+        draw_fn(&mut painter);
+        timer.record_time("cmd");
+
+        let surface_texture = match render_surface.try_current_surface_texture() {
+            Ok(surface_texture) => surface_texture,
+            Err(err) => {
+                // Skip this frame, recreating the surface first if it has been lost. Image uploads
+                // are still submitted as the uploaded images remain cached.
+                render_surface.queue().submit([encoder.finish()]);
+                self.scene.reset();
+                match err {
+                    SurfaceError::Lost => self.recover(),
+                    SurfaceError::Outdated | SurfaceError::Timeout => {}
+                    err => return Err(err),
+                }
+                return Ok(());
+            }
+        };
+        let texture_view = surface_texture
+            .texture
+            .create_view(&TextureViewDescriptor::default());
+
+        state
+            .renderer
+            .render(
+                &self.scene,
+                render_surface.device(),
+                render_surface.queue(),
+                &mut encoder,
+                &RenderSize {
+                    width: render_surface.config.width,
+                    height: render_surface.config.height,
+                },
+                &texture_view,
+            )
+            .expect("failed to render to texture");
+
+        // Evict images which are no longer being drawn
+        self.cached_images.next_gen(
+            &mut state.renderer,
+            render_surface.device(),
+            render_surface.queue(),
+            &mut encoder,
+        );
+        self.cached_box_shadows.next_gen(drop);
+
+        render_surface.queue().submit([encoder.finish()]);
+        timer.record_time("render");
+
+        drop(texture_view);
+        surface_texture.present();
+        timer.record_time("present");
+
+        render_surface.device().poll(wgpu::PollType::Wait).unwrap();
+
+        timer.record_time("wait");
+        timer.print_times("vello_hybrid: ");
+
+        // static COUNTER: AtomicU64 = AtomicU64::new(0);
+        // println!("FRAME {}", COUNTER.fetch_add(1, atomic::Ordering::Relaxed));
+
+        // Empty the Vello scene (memory optimisation)
+        self.scene.reset();
+
+        Ok(())
+    }
 }
 
 impl WindowRenderer for VelloHybridWindowRenderer {
@@ -124,14 +375,12 @@ impl WindowRenderer for VelloHybridWindowRenderer {
             window_handle.clone(),
             SurfaceRendererConfiguration {
                 usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                formats: vec![
-                    /*TextureFormat::Rgba8Unorm, */ TextureFormat::Bgra8Unorm,
-                ],
+                formats: self.surface_formats(),
                 width,
                 height,
-                present_mode: PresentMode::AutoVsync,
-                desired_maximum_frame_latency: 2,
-                alpha_mode: wgpu::CompositeAlphaMode::Auto,
+                present_mode: self.config.present_mode,
+                desired_maximum_frame_latency: self.config.desired_maximum_frame_latency,
+                alpha_mode: self.config.alpha_mode,
                 view_formats: vec![],
             },
             None,
@@ -145,8 +394,7 @@ impl WindowRenderer for VelloHybridWindowRenderer {
         let renderer = VelloHybridRenderer::new(
             render_surface.device(),
             &RenderTargetConfig {
-                // TODO: Make configurable?
-                format: TextureFormat::Bgra8Unorm,
+                format: render_surface.config.format,
                 width,
                 height,
             },
@@ -172,6 +420,9 @@ impl WindowRenderer for VelloHybridWindowRenderer {
         //     source.suspend()
         // }
 
+        // Images are uploaded to the renderer's atlas, so must be re-uploaded to the next renderer
+        self.cached_images.clear();
+
         // Set state to Suspended
         self.render_state = RenderState::Suspended;
     }
@@ -190,73 +441,12 @@ impl WindowRenderer for VelloHybridWindowRenderer {
     }
 
     fn render<F: FnOnce(&mut Self::ScenePainter<'_>)>(&mut self, draw_fn: F) {
-        let RenderState::Active(state) = &mut self.render_state else {
-            return;
-        };
-
-        let render_surface = &state.render_surface;
-
-        debug_timer!(timer, feature = "log_frame_times");
-
-        let mut encoder =
-            render_surface
-                .device()
-                .create_command_encoder(&CommandEncoderDescriptor {
-                    label: Some("Render scene"),
-                });
-
-        let image_manager = ImageManager {
-            renderer: &mut state.renderer,
-            device: render_surface.device(),
-            queue: render_surface.queue(),
-            encoder: &mut encoder,
-            cache: &mut self.cached_images,
-        };
-
-        // Regenerate the vello scene
-        draw_fn(&mut VelloHybridScenePainter {
-            scene: &mut self.scene,
-            image_manager: Some(image_manager),
-        });
-        timer.record_time("cmd");
-
-        let surface_texture = render_surface.current_surface_texture();
-        let texture_view = surface_texture
-            .texture
-            .create_view(&TextureViewDescriptor::default());
-
-        state
-            .renderer
-            .render(
-                &self.scene,
-                render_surface.device(),
-                render_surface.queue(),
-                &mut encoder,
-                &RenderSize {
-                    width: render_surface.config.width,
-                    height: render_surface.config.height,
-                },
-                &texture_view,
-            )
-            .expect("failed to render to texture");
-        render_surface.queue().submit([encoder.finish()]);
-        timer.record_time("render");
-
-        drop(texture_view);
-        drop(surface_texture);
-
-        render_surface.maybe_blit_and_present();
-        timer.record_time("present");
-
-        render_surface.device().poll(wgpu::PollType::Wait).unwrap();
-
-        timer.record_time("wait");
-        timer.print_times("vello_hybrid: ");
-
-        // static COUNTER: AtomicU64 = AtomicU64::new(0);
-        // println!("FRAME {}", COUNTER.fetch_add(1, atomic::Ordering::Relaxed));
+        if let Err(err) = self.try_render(draw_fn) {
+            eprintln!("WARNING: failed to render frame: {err}");
+        }
+    }
 
-        // Empty the Vello scene (memory optimisation)
-        self.scene.reset();
+    fn set_base_color(&mut self, color: Color) {
+        self.config.base_color = color;
     }
 }
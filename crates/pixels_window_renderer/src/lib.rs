@@ -4,7 +4,8 @@
 
 use anyrender::{ImageRenderer, WindowHandle, WindowRenderer};
 use debug_timer::debug_timer;
-use pixels::{Pixels, SurfaceTexture, wgpu::Color};
+use peniko::Color;
+use pixels::{Pixels, SurfaceTexture, wgpu};
 use std::sync::Arc;
 
 // Simple struct to hold the state of the renderer
@@ -56,7 +57,7 @@ impl<Renderer: ImageRenderer> WindowRenderer for PixelsWindowRenderer<Renderer>
         let surface = SurfaceTexture::new(width, height, window_handle.clone());
         let mut pixels = Pixels::new(width, height, surface).unwrap();
         pixels.enable_vsync(true);
-        pixels.clear_color(Color {
+        pixels.clear_color(wgpu::Color {
             r: 1.0,
             g: 1.0,
             b: 1.0,
@@ -104,4 +105,8 @@ impl<Renderer: ImageRenderer> WindowRenderer for PixelsWindowRenderer<Renderer>
         // Reset the renderer ready for the next render
         self.renderer.reset();
     }
+
+    fn set_base_color(&mut self, color: Color) {
+        self.renderer.set_base_color(color);
+    }
 }
@@ -4,6 +4,7 @@
 
 use anyrender::{ImageRenderer, WindowHandle, WindowRenderer};
 use debug_timer::debug_timer;
+use peniko::Color;
 use softbuffer::{Context, Surface};
 use std::{num::NonZero, sync::Arc};
 
@@ -122,4 +123,8 @@ impl<Renderer: ImageRenderer> WindowRenderer for SoftbufferWindowRenderer<Render
         // Reset the renderer ready for the next render
         self.renderer.reset();
     }
+
+    fn set_base_color(&mut self, color: Color) {
+        self.renderer.set_base_color(color);
+    }
 }
@@ -0,0 +1,204 @@
+use crate::WgpuContextError;
+use std::sync::Arc;
+use wgpu::{
+    Adapter, AdapterInfo, Backends, DeviceType, Instance, PowerPreference, RequestAdapterOptions,
+    Surface,
+};
+
+/// A callback which ranks candidate adapters.
+///
+/// Adapters for which `None` is returned are rejected. Of the remaining adapters, the one with
+/// the highest rank is selected (ties are broken by enumeration order).
+pub type AdapterRankFn = Arc<dyn Fn(&AdapterInfo) -> Option<i64> + Send + Sync>;
+
+/// Policy for choosing which adapter (GPU) devices are created on
+#[derive(Clone)]
+pub struct AdapterSelection {
+    /// Whether to prefer integrated or discrete GPUs.
+    ///
+    /// Defaults to the value of the `WGPU_POWER_PREF` environment variable (if set).
+    pub power_preference: PowerPreference,
+    /// Only select fallback (software) adapters
+    pub force_fallback_adapter: bool,
+    /// Only select adapters whose name contains this string (case-insensitive).
+    ///
+    /// Defaults to the value of the `WGPU_ADAPTER_NAME` environment variable (if set).
+    pub name_filter: Option<String>,
+    /// Only select adapters with this PCI vendor id
+    pub vendor_filter: Option<u32>,
+    /// Only select adapters from these backends
+    pub backends: Backends,
+    /// Custom ranking for candidate adapters. Overrides `power_preference` if set.
+    pub rank: Option<AdapterRankFn>,
+    /// Retry with a fallback (software) adapter if no other adapter matches this policy
+    pub fallback_to_software: bool,
+}
+
+impl Default for AdapterSelection {
+    fn default() -> Self {
+        Self {
+            power_preference: PowerPreference::from_env().unwrap_or_default(),
+            force_fallback_adapter: false,
+            name_filter: std::env::var("WGPU_ADAPTER_NAME").ok(),
+            vendor_filter: None,
+            backends: Backends::all(),
+            rank: None,
+            fallback_to_software: true,
+        }
+    }
+}
+
+impl std::fmt::Debug for AdapterSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdapterSelection")
+            .field("power_preference", &self.power_preference)
+            .field("force_fallback_adapter", &self.force_fallback_adapter)
+            .field("name_filter", &self.name_filter)
+            .field("vendor_filter", &self.vendor_filter)
+            .field("backends", &self.backends)
+            .field("has_rank", &self.rank.is_some())
+            .field("fallback_to_software", &self.fallback_to_software)
+            .finish()
+    }
+}
+
+impl AdapterSelection {
+    /// Selects a fallback (software) adapter. Useful for deterministic rendering in CI.
+    pub fn fallback() -> Self {
+        Self {
+            force_fallback_adapter: true,
+            ..Default::default()
+        }
+    }
+
+    /// Whether this policy can be fulfilled by `Instance::request_adapter` alone
+    fn is_simple(&self) -> bool {
+        self.name_filter.is_none()
+            && self.vendor_filter.is_none()
+            && self.rank.is_none()
+            && self.backends == Backends::all()
+    }
+
+    /// Selects an adapter according to this policy.
+    ///
+    /// If no adapter matches and `fallback_to_software` is set then a fallback adapter is selected instead.
+    pub async fn select_adapter(
+        &self,
+        instance: &Instance,
+        compatible_surface: Option<&Surface<'_>>,
+    ) -> Result<Adapter, WgpuContextError> {
+        match self
+            .select_adapter_inner(instance, compatible_surface)
+            .await
+        {
+            Err(err) if self.fallback_to_software && !self.force_fallback_adapter => {
+                let fallback = Self {
+                    force_fallback_adapter: true,
+                    ..self.clone()
+                };
+                fallback
+                    .select_adapter_inner(instance, compatible_surface)
+                    .await
+                    .map_err(|_| err)
+            }
+            result => result,
+        }
+    }
+
+    async fn select_adapter_inner(
+        &self,
+        instance: &Instance,
+        compatible_surface: Option<&Surface<'_>>,
+    ) -> Result<Adapter, WgpuContextError> {
+        // Adapter enumeration is not available on the web, so filters and ranking are ignored there.
+        if self.is_simple() || cfg!(target_arch = "wasm32") {
+            let adapter = instance
+                .request_adapter(&RequestAdapterOptions {
+                    power_preference: self.power_preference,
+                    force_fallback_adapter: self.force_fallback_adapter,
+                    compatible_surface,
+                })
+                .await?;
+            return Ok(adapter);
+        }
+
+        self.select_enumerated_adapter(instance, compatible_surface)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn select_enumerated_adapter(
+        &self,
+        instance: &Instance,
+        compatible_surface: Option<&Surface<'_>>,
+    ) -> Result<Adapter, WgpuContextError> {
+        let mut best: Option<(i64, Adapter)> = None;
+        for adapter in instance.enumerate_adapters(self.backends) {
+            if !self.is_acceptable(&adapter, compatible_surface) {
+                continue;
+            }
+            let Some(rank) = self.rank(&adapter.get_info()) else {
+                continue;
+            };
+            // Only replace on a strictly higher rank so that ties keep enumeration order
+            if best.as_ref().is_none_or(|(best_rank, _)| rank > *best_rank) {
+                best = Some((rank, adapter));
+            }
+        }
+        best.map(|(_, adapter)| adapter)
+            .ok_or(WgpuContextError::NoCompatibleDevice)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn select_enumerated_adapter(
+        &self,
+        _instance: &Instance,
+        _compatible_surface: Option<&Surface<'_>>,
+    ) -> Result<Adapter, WgpuContextError> {
+        unreachable!("adapters are always requested directly on the web")
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn is_acceptable(&self, adapter: &Adapter, compatible_surface: Option<&Surface<'_>>) -> bool {
+        let info = adapter.get_info();
+
+        if compatible_surface.is_some_and(|surface| !adapter.is_surface_supported(surface)) {
+            return false;
+        }
+        if self.force_fallback_adapter && info.device_type != DeviceType::Cpu {
+            return false;
+        }
+        let name_filter = self.name_filter.as_ref().map(|name| name.to_lowercase());
+        if name_filter.is_some_and(|name| !info.name.to_lowercase().contains(&name)) {
+            return false;
+        }
+        if self
+            .vendor_filter
+            .is_some_and(|vendor| info.vendor != vendor)
+        {
+            return false;
+        }
+
+        true
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn rank(&self, info: &AdapterInfo) -> Option<i64> {
+        if let Some(rank) = &self.rank {
+            return rank(info);
+        }
+
+        // Mirrors the ordering used by `Instance::request_adapter`. More specific device types are
+        // preferred over `Other` as it may come from APIs (like OpenGL) that don't report a device type.
+        let rank = match (self.power_preference, info.device_type) {
+            (PowerPreference::None, _) => 0,
+            (PowerPreference::HighPerformance, DeviceType::DiscreteGpu) => 5,
+            (PowerPreference::HighPerformance, DeviceType::IntegratedGpu) => 4,
+            (PowerPreference::LowPower, DeviceType::IntegratedGpu) => 5,
+            (PowerPreference::LowPower, DeviceType::DiscreteGpu) => 4,
+            (_, DeviceType::Other) => 3,
+            (_, DeviceType::VirtualGpu) => 2,
+            (_, DeviceType::Cpu) => 1,
+        };
+        Some(rank)
+    }
+}
@@ -0,0 +1,17 @@
+// Copies pixels 1:1 from the top-left region of the source texture to the target.
+// Unlike `wgpu::util::TextureBlitter` this does not scale, so the source may be larger than the target.
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    // A single triangle which covers the whole target
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(source, vec2<u32>(position.xy), 0);
+}
@@ -1,4 +1,4 @@
-use crate::{DeviceHandle, block_on_wgpu, util::create_texture};
+use crate::{DeviceHandle, block_on_wgpu, resize::ResizeTracker, util::create_texture};
 use wgpu::{
     BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Device, Extent3d, Queue,
     TexelCopyBufferInfo, TexelCopyBufferLayout, TextureFormat, TextureUsages, TextureView,
@@ -18,6 +18,8 @@ pub struct BufferRenderer {
     pub device_handle: DeviceHandle,
 
     config: BufferRendererConfig,
+    // Once resized, the texture and buffer are over-allocated (see `ResizeTracker`), so only their top-left region is used
+    allocation: ResizeTracker,
     texture_view: wgpu::TextureView,
     gpu_buffer: wgpu::Buffer,
 }
@@ -34,30 +36,56 @@ impl std::fmt::Debug for BufferRenderer {
 impl BufferRenderer {
     /// Creates a new render surface for the specified window and dimensions.
     pub fn new(config: BufferRendererConfig, device_handle: DeviceHandle, dev_id: usize) -> Self {
-        let texture_view = create_texture(
+        let allocation = ResizeTracker::new(
             config.width,
             config.height,
+            device_handle.device.limits().max_texture_dimension_2d,
+        );
+        let (texture_view, gpu_buffer) =
+            Self::allocate(&config, allocation.allocated_size(), &device_handle.device);
+
+        Self {
+            dev_id,
+            device_handle,
+            config,
+            allocation,
+            texture_view,
+            gpu_buffer,
+        }
+    }
+
+    fn allocate(
+        config: &BufferRendererConfig,
+        (width, height): (u32, u32),
+        device: &Device,
+    ) -> (TextureView, wgpu::Buffer) {
+        let texture_view = create_texture(
+            width,
+            height,
             TextureFormat::Rgba8Unorm,
             config.usage | TextureUsages::COPY_SRC,
-            &device_handle.device,
+            &[],
+            device,
         );
 
-        let padded_byte_width = (config.width * 4).next_multiple_of(256);
-        let buffer_size = padded_byte_width as u64 * config.height as u64;
-        let gpu_buffer = device_handle.device.create_buffer(&BufferDescriptor {
+        let padded_byte_width = (width * 4).next_multiple_of(256);
+        let buffer_size = padded_byte_width as u64 * height as u64;
+        let gpu_buffer = device.create_buffer(&BufferDescriptor {
             label: None,
             size: buffer_size,
             usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
-        Self {
-            dev_id,
-            device_handle,
-            config,
-            texture_view,
-            gpu_buffer,
-        }
+        (texture_view, gpu_buffer)
+    }
+
+    fn reallocate(&mut self) {
+        (self.texture_view, self.gpu_buffer) = Self::allocate(
+            &self.config,
+            self.allocation.allocated_size(),
+            &self.device_handle.device,
+        );
     }
 
     pub fn device(&self) -> &Device {
@@ -76,51 +104,24 @@ impl BufferRenderer {
         }
     }
 
+    /// Resizes the renderer. The texture and buffer are only reallocated if they are too small for the new dimensions.
     pub fn resize(&mut self, width: u32, height: u32) {
         self.config.width = width;
         self.config.height = height;
-        self.texture_view = create_texture(
-            self.config.width,
-            self.config.height,
-            TextureFormat::Rgba8Unorm,
-            self.config.usage | TextureUsages::COPY_SRC,
-            &self.device_handle.device,
-        );
-
-        let padded_byte_width = (width * 4).next_multiple_of(256);
-        let buffer_size = padded_byte_width as u64 * height as u64;
-        self.gpu_buffer = self.device_handle.device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: buffer_size,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        if self.allocation.resize(width, height) {
+            self.reallocate();
+        }
     }
 
-    // /// Resizes the surface to the new dimensions.
-    // pub fn resize(&mut self, width: u32, height: u32) {
-    //     // TODO: Use clever resize semantics to avoid thrashing the memory allocator during a resize
-    //     // especially important on metal.
-    //     if let Some(intermediate_texture_stuff) = &mut self.intermediate_texture {
-    //         intermediate_texture_stuff.texture_view = create_intermediate_texture(
-    //             width,
-    //             height,
-    //             intermediate_texture_stuff.config.usage,
-    //             &self.device_handle.device,
-    //         );
-    //     }
-    //     self.config.width = width;
-    //     self.config.height = height;
-    //     self.configure();
-    // }
-
+    /// The view to render to. This may be larger than the renderer's size, in which case
+    /// only the top-left region with the renderer's size will be copied out.
     pub fn target_texture_view(&self) -> TextureView {
         self.texture_view.clone()
     }
 
     pub fn copy_texture_to_vec(&self, cpu_buffer: &mut Vec<u8>) {
         cpu_buffer.clear();
-        cpu_buffer.reserve((self.config.width * self.config.height * 4) as usize);
+        cpu_buffer.resize((self.config.width * self.config.height * 4) as usize, 0);
         self.copy_texture_to_buffer(&mut *cpu_buffer);
     }
 
@@ -144,7 +145,7 @@ impl BufferRenderer {
                     rows_per_image: None,
                 },
             },
-            texture.size(),
+            self.size(),
         );
 
         self.queue().submit([encoder.finish()]);
@@ -176,4 +177,12 @@ impl BufferRenderer {
         drop(data);
         self.gpu_buffer.unmap();
     }
+
+    /// Records that a frame has been rendered and copied out. The texture and buffer are shrunk if
+    /// they have been larger than needed for a while, so this should be called once per frame.
+    pub fn end_frame(&mut self) {
+        if self.allocation.end_frame() {
+            self.reallocate();
+        }
+    }
 }
@@ -12,11 +12,8 @@ pub enum WgpuContextError {
     /// Failed to create surface.
     /// See [`wgpu::CreateSurfaceError`] for more information.
     WgpuCreateSurfaceError(wgpu::CreateSurfaceError),
-    /// Surface doesn't support the required texture formats.
-    /// Make sure that you have a surface which provides one of
-    /// `TextureFormat::Rgba8Unorm`
-    /// or `TextureFormat::Bgra8Unorm` as texture formats.
-    // TODO: Why does this restriction exist?
+    /// Surface doesn't support any of the requested texture formats.
+    /// See [`crate::SurfaceRendererConfiguration::formats`].
     UnsupportedSurfaceFormat,
     /// Wgpu failed to request an adapter
     RequestAdapterError(RequestAdapterError),
@@ -24,6 +21,8 @@ pub enum WgpuContextError {
     RequestDeviceError(RequestDeviceError),
     /// Wgpu failed to poll a device
     PollError(PollError),
+    /// Failed to read or write pipeline cache data
+    PipelineCacheIoError(std::io::Error),
 }
 
 impl Display for WgpuContextError {
@@ -37,7 +36,7 @@ impl Display for WgpuContextError {
             Self::UnsupportedSurfaceFormat => {
                 writeln!(
                     f,
-                    "Couldn't find `Rgba8Unorm` or `Bgra8Unorm` texture formats for surface"
+                    "Couldn't find any of the requested texture formats for surface"
                 )
             }
             Self::RequestAdapterError(inner) => {
@@ -49,6 +48,9 @@ impl Display for WgpuContextError {
             Self::PollError(inner) => {
                 writeln!(f, "Couldn't poll a device: {:#}", inner)
             }
+            Self::PipelineCacheIoError(inner) => {
+                writeln!(f, "Couldn't read or write pipeline cache: {:#}", inner)
+            }
         }
     }
 }
@@ -77,3 +79,9 @@ impl From<PollError> for WgpuContextError {
         Self::PollError(value)
     }
 }
+
+impl From<std::io::Error> for WgpuContextError {
+    fn from(value: std::io::Error) -> Self {
+        Self::PipelineCacheIoError(value)
+    }
+}
@@ -3,21 +3,34 @@
 
 //! Simple helpers for managing wgpu state and surfaces.
 
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
 use wgpu::{
-    Adapter, Device, Features, Instance, Limits, MemoryHints, Queue, Surface, SurfaceTarget,
+    Adapter, Device, DeviceLostReason, DeviceType, Features, Instance, Limits, MemoryHints, Queue,
+    Surface, SurfaceTarget,
 };
 
+mod adapter_selection;
+mod blit;
 mod buffer_renderer;
 mod error;
+mod pipeline_cache;
+mod resize;
 mod surface_renderer;
+mod texture_renderer;
 mod util;
 
+pub use adapter_selection::{AdapterRankFn, AdapterSelection};
 pub use buffer_renderer::{BufferRenderer, BufferRendererConfig};
 pub use error::WgpuContextError;
-pub use surface_renderer::{SurfaceRenderer, SurfaceRendererConfiguration, TextureConfiguration};
+pub use pipeline_cache::{PersistentPipelineCache, PipelineCacheManager};
+pub use surface_renderer::{
+    SurfaceColorSpace, SurfaceRenderer, SurfaceRendererConfiguration, TextureConfiguration,
+};
+pub use texture_renderer::{TextureRenderer, TextureRendererConfig};
 pub use util::block_on_wgpu;
 
-/// A wgpu `Device`, it's associated `Queue`, and the `Adapter` and `Instance` used to create them
+/// A wgpu `Device`, its associated `Queue`, and the `Adapter` and `Instance` used to create them
 #[derive(Clone, Debug)]
 pub struct DeviceHandle {
     pub instance: Instance,
@@ -26,6 +39,81 @@ pub struct DeviceHandle {
     pub queue: Queue,
 }
 
+/// The devices which have been lost, identified by [`device_key`]. This is kept outside of
+/// `DeviceHandle` so that handles can still be constructed from their fields. Devices are removed
+/// once they have been replaced in a [`WGPUContext`]'s device pool.
+static LOST_DEVICES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+fn device_key(device: &Device) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    device.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl DeviceHandle {
+    pub fn new(instance: Instance, adapter: Adapter, device: Device, queue: Queue) -> Self {
+        Self {
+            instance,
+            adapter,
+            device,
+            queue,
+        }
+    }
+
+    /// Registers a device lost callback on the device which calls [`DeviceHandle::mark_lost`] if the
+    /// device is lost, so that the loss can be detected using [`DeviceHandle::is_lost`].
+    ///
+    /// This replaces any existing device lost callback. Applications which register their own
+    /// callback should call [`DeviceHandle::mark_lost`] from it instead. Devices created by
+    /// [`WGPUContext`] are registered automatically.
+    pub fn detect_device_loss(&self) {
+        let key = device_key(&self.device);
+        self.device
+            .set_device_lost_callback(move |reason, message| {
+                // `Destroyed` means that the device was intentionally destroyed, which is not a loss
+                // that can or should be recovered from.
+                if reason == DeviceLostReason::Unknown {
+                    eprintln!("WARNING: wgpu device lost: {message}");
+                    mark_device_lost(key);
+                }
+            });
+    }
+
+    /// Records that the device has been lost. Renderers using the device will recreate their
+    /// surfaces using a new device.
+    pub fn mark_lost(&self) {
+        mark_device_lost(device_key(&self.device));
+    }
+
+    /// Whether the device has been lost (e.g. due to a driver reset or crash).
+    ///
+    /// A lost device can no longer be used for rendering. Everything created from it (surfaces,
+    /// textures, pipelines, etc) must be recreated using a new device.
+    pub fn is_lost(&self) -> bool {
+        let key = device_key(&self.device);
+        LOST_DEVICES.lock().unwrap().contains(&key)
+    }
+
+    /// Whether the device is backed by a fallback (software) adapter rather than a GPU
+    pub fn is_software(&self) -> bool {
+        self.adapter.get_info().device_type == DeviceType::Cpu
+    }
+}
+
+fn mark_device_lost(key: u64) {
+    let mut lost_devices = LOST_DEVICES.lock().unwrap();
+    if !lost_devices.contains(&key) {
+        lost_devices.push(key);
+    }
+}
+
+fn forget_lost_device(key: u64) {
+    LOST_DEVICES
+        .lock()
+        .unwrap()
+        .retain(|&lost_key| lost_key != key);
+}
+
 /// Simple render context that maintains wgpu state for rendering the pipeline.
 pub struct WGPUContext {
     /// A WGPU `Instance`. This only needs to be created once per application.
@@ -37,6 +125,7 @@ pub struct WGPUContext {
     // Config
     extra_features: Option<Features>,
     override_limits: Option<Limits>,
+    adapter_selection: AdapterSelection,
 }
 
 impl Default for WGPUContext {
@@ -64,9 +153,51 @@ impl WGPUContext {
             device_pool: Vec::new(),
             extra_features,
             override_limits,
+            adapter_selection: AdapterSelection::default(),
         }
     }
 
+    /// Sets the policy used to choose an adapter when creating new devices.
+    ///
+    /// Devices which have already been created are not affected.
+    pub fn set_adapter_selection(&mut self, adapter_selection: AdapterSelection) {
+        self.adapter_selection = adapter_selection;
+    }
+
+    /// Creates a context which adopts an existing device (and the instance it was created from)
+    /// rather than creating its own.
+    ///
+    /// The adopted device will be used for all surfaces and buffers that it is compatible with.
+    /// A new device will only be created if it is not compatible with a surface, or if it is lost.
+    /// New devices are created with the adopted device's features and limits.
+    ///
+    /// Loss of the adopted device is only detected if [`DeviceHandle::detect_device_loss`] has been
+    /// called, or if the application calls [`DeviceHandle::mark_lost`] from its own callback.
+    pub fn with_device_handle(device_handle: DeviceHandle) -> Self {
+        Self {
+            instance: device_handle.instance.clone(),
+            extra_features: Some(device_handle.device.features()),
+            override_limits: Some(device_handle.device.limits()),
+            device_pool: vec![device_handle],
+            adapter_selection: AdapterSelection::default(),
+        }
+    }
+
+    /// Adds an externally created device to the device pool, returning its id.
+    ///
+    /// The device must have been created from this context's `instance` in order to
+    /// be used to render to surfaces created by this context. Devices created by this context later on
+    /// also request the device's features (and its limits, unless limits have been specified).
+    pub fn add_device(&mut self, device_handle: DeviceHandle) -> usize {
+        let features = device_handle.device.features();
+        self.extra_features = Some(self.extra_features.unwrap_or(Features::empty()) | features);
+        if self.override_limits.is_none() {
+            self.override_limits = Some(device_handle.device.limits());
+        }
+        self.device_pool.push(device_handle);
+        self.device_pool.len() - 1
+    }
+
     /// Creates a new surface for the specified window and dimensions.
     pub async fn create_surface<'w>(
         &mut self,
@@ -109,6 +240,21 @@ impl WGPUContext {
         Ok(BufferRenderer::new(config, device_handle, dev_id))
     }
 
+    /// Creates a new `TextureRenderer` for the specified dimensions.
+    pub async fn create_texture_renderer(
+        &mut self,
+        config: TextureRendererConfig,
+    ) -> Result<TextureRenderer, WgpuContextError> {
+        // Find or create a suitable device for rendering to the texture
+        let dev_id = self
+            .find_or_create_device(None)
+            .await
+            .or(Err(WgpuContextError::NoCompatibleDevice))?;
+        let device_handle = self.device_pool[dev_id].clone();
+
+        Ok(TextureRenderer::new(config, device_handle, dev_id))
+    }
+
     /// Finds or creates a compatible device handle id.
     pub async fn find_or_create_device(
         &mut self,
@@ -120,17 +266,24 @@ impl WGPUContext {
         }
     }
 
-    /// Finds or creates a compatible device handle id.
-    fn find_existing_device(&self, compatible_surface: Option<&Surface<'_>>) -> Option<usize> {
-        match compatible_surface {
-            Some(s) => self
+    /// Whether a device handle obtained from this context can no longer be used, because its device
+    /// has been lost or has been replaced in the device pool (which happens after it was lost).
+    pub fn is_device_lost(&self, device_handle: &DeviceHandle) -> bool {
+        device_handle.is_lost()
+            || !self
                 .device_pool
                 .iter()
-                .enumerate()
-                .find(|(_, d)| d.adapter.is_surface_supported(s))
-                .map(|(i, _)| i),
-            None => (!self.device_pool.is_empty()).then_some(0),
-        }
+                .any(|d| d.device == device_handle.device)
+    }
+
+    /// Finds a compatible device handle id. Lost devices are never returned.
+    fn find_existing_device(&self, compatible_surface: Option<&Surface<'_>>) -> Option<usize> {
+        self.device_pool
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| !d.is_lost())
+            .find(|(_, d)| compatible_surface.is_none_or(|s| d.adapter.is_surface_supported(s)))
+            .map(|(i, _)| i)
     }
 
     /// Creates a compatible device handle id.
@@ -139,9 +292,10 @@ impl WGPUContext {
         compatible_surface: Option<&Surface<'_>>,
     ) -> Result<usize, WgpuContextError> {
         let instance = self.instance.clone();
-        let adapter =
-            wgpu::util::initialize_adapter_from_env_or_default(&instance, compatible_surface)
-                .await?;
+        let adapter = self
+            .adapter_selection
+            .select_adapter(&instance, compatible_surface)
+            .await?;
 
         // Determine features to request
         // The user may request additional features
@@ -164,15 +318,22 @@ impl WGPUContext {
         let (device, queue) = adapter.request_device(&descripter).await?;
 
         // Create the device handle and store in the pool
-        let device_handle = DeviceHandle {
-            instance,
-            adapter,
-            device,
-            queue,
-        };
-        self.device_pool.push(device_handle);
-
-        // Return the ID
-        Ok(self.device_pool.len() - 1)
+        // (replacing a lost device if there is one, so that the pool doesn't grow unboundedly)
+        let device_handle = DeviceHandle::new(instance, adapter, device, queue);
+        device_handle.detect_device_loss();
+        match self.device_pool.iter().position(|d| d.is_lost()) {
+            Some(dev_id) => {
+                // Users of the replaced device detect this using `is_device_lost`, so it no longer
+                // needs to be remembered as lost
+                let lost_device_handle =
+                    std::mem::replace(&mut self.device_pool[dev_id], device_handle);
+                forget_lost_device(device_key(&lost_device_handle.device));
+                Ok(dev_id)
+            }
+            None => {
+                self.device_pool.push(device_handle);
+                Ok(self.device_pool.len() - 1)
+            }
+        }
     }
 }
@@ -0,0 +1,111 @@
+use crate::{DeviceHandle, WgpuContextError};
+use std::path::{Path, PathBuf};
+use wgpu::{AdapterInfo, Features, PipelineCache, PipelineCacheDescriptor};
+
+/// The major version of wgpu that this crate is built against.
+///
+/// Pipeline cache data is an implementation detail of wgpu, so data written by one version of wgpu
+/// must not be fed to another. This MUST be kept in sync with the `wgpu` version in `Cargo.toml`.
+const WGPU_VERSION: &str = "26";
+
+/// Loads and saves `wgpu::PipelineCache` data to a caller-chosen directory.
+///
+/// Cache files are keyed by the adapter they were created for and the version of wgpu in use,
+/// so a single directory can safely be shared between multiple GPUs and application versions.
+#[derive(Clone, Debug)]
+pub struct PipelineCacheManager {
+    directory: PathBuf,
+}
+
+impl PipelineCacheManager {
+    /// Creates a new `PipelineCacheManager` which stores cache files in `directory`.
+    ///
+    /// The directory will be created when the cache is first saved if it does not already exist.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// The directory in which cache files are stored
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The key used to identify pipeline cache data for an adapter.
+    ///
+    /// Returns `None` if pipeline caching is not supported for the adapter's backend.
+    pub fn cache_key(adapter_info: &AdapterInfo) -> Option<String> {
+        let key = wgpu::util::pipeline_cache_key(adapter_info)?;
+        Some(format!("{key}_wgpu{WGPU_VERSION}"))
+    }
+
+    /// Creates a `PipelineCache` for the device, seeded with previously saved data if available.
+    ///
+    /// Returns `None` if the device was not created with [`Features::PIPELINE_CACHE`] or if
+    /// pipeline caching is not supported for the device's backend.
+    pub fn load(&self, device_handle: &DeviceHandle) -> Option<PersistentPipelineCache> {
+        if !device_handle
+            .device
+            .features()
+            .contains(Features::PIPELINE_CACHE)
+        {
+            return None;
+        }
+
+        let key = Self::cache_key(&device_handle.adapter.get_info())?;
+        let path = self.directory.join(key);
+
+        // A missing or unreadable file simply results in an empty cache
+        let data = std::fs::read(&path).ok();
+
+        // SAFETY: The data was written by `PersistentPipelineCache::save` using `PipelineCache::get_data`
+        // and the file name is keyed on both the adapter and the wgpu version. Invalid data falls back
+        // to an empty cache.
+        let cache = unsafe {
+            device_handle
+                .device
+                .create_pipeline_cache(&PipelineCacheDescriptor {
+                    label: Some("anyrender pipeline cache"),
+                    data: data.as_deref(),
+                    fallback: true,
+                })
+        };
+
+        Some(PersistentPipelineCache { cache, path })
+    }
+}
+
+/// A `wgpu::PipelineCache` along with the path that its data should be persisted to
+#[derive(Clone, Debug)]
+pub struct PersistentPipelineCache {
+    pub cache: PipelineCache,
+    path: PathBuf,
+}
+
+impl PersistentPipelineCache {
+    /// The path that the cache data is loaded from and saved to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the current cache data to disk.
+    ///
+    /// The data is written to a temporary file which is then renamed over the existing cache file,
+    /// so that a crash during saving cannot leave a partially written cache behind.
+    pub fn save(&self) -> Result<(), WgpuContextError> {
+        let Some(data) = self.cache.get_data() else {
+            return Ok(());
+        };
+
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = self.path.with_extension("tmp");
+        std::fs::write(&temp_path, &data)?;
+        std::fs::rename(&temp_path, &self.path)?;
+
+        Ok(())
+    }
+}
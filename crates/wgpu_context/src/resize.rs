@@ -0,0 +1,173 @@
+/// Allocated dimensions are rounded up to a multiple of this many pixels
+const BUCKET_SIZE: u32 = 256;
+
+/// The number of consecutive frames for which an allocation must be larger than required before it is shrunk
+const SHRINK_DELAY_FRAMES: u32 = 60;
+
+/// Decides when textures (and buffers) which track the size of a window need to be reallocated.
+///
+/// Allocations start out at exactly the requested size. Once resized, they are over-allocated to a multiple
+/// of [`BUCKET_SIZE`] so that resizing (e.g. dragging the edge of a window) only reallocates when a bucket
+/// boundary is crossed. The used region of the allocation is rendered to and copied out as a sub-rect. Allocations are only shrunk once the size has been stable for
+/// [`SHRINK_DELAY_FRAMES`] frames, so that shrinking and then growing again doesn't thrash the allocator.
+#[derive(Clone, Debug)]
+pub(crate) struct ResizeTracker {
+    allocated_width: u32,
+    allocated_height: u32,
+    /// The maximum size of a texture dimension on the device
+    max_dimension: u32,
+    /// The number of consecutive frames for which the allocation has been larger than required
+    oversized_frames: u32,
+    /// Whether the requested size has ever changed. Targets which are never resized (e.g. one-shot image
+    /// renderers) aren't over-allocated.
+    resized: bool,
+    width: u32,
+    height: u32,
+}
+
+impl ResizeTracker {
+    pub(crate) fn new(width: u32, height: u32, max_dimension: u32) -> Self {
+        let mut tracker = Self {
+            allocated_width: 0,
+            allocated_height: 0,
+            max_dimension,
+            oversized_frames: 0,
+            resized: false,
+            width,
+            height,
+        };
+        tracker.allocated_width = tracker.bucket(width);
+        tracker.allocated_height = tracker.bucket(height);
+        tracker
+    }
+
+    /// The size that should currently be allocated
+    pub(crate) fn allocated_size(&self) -> (u32, u32) {
+        (self.allocated_width, self.allocated_height)
+    }
+
+    /// Records a new requested size. Returns `true` if the allocation needs to grow.
+    pub(crate) fn resize(&mut self, width: u32, height: u32) -> bool {
+        if width != self.width || height != self.height {
+            self.resized = true;
+        }
+        self.width = width;
+        self.height = height;
+        self.oversized_frames = 0;
+
+        if width <= self.allocated_width && height <= self.allocated_height {
+            return false;
+        }
+
+        // The other dimension is never shrunk here. That is left to deferred shrinking.
+        self.allocated_width = self.allocated_width.max(self.bucket(width));
+        self.allocated_height = self.allocated_height.max(self.bucket(height));
+        true
+    }
+
+    /// Records that a frame has been rendered. Returns `true` if the allocation has been larger than
+    /// required for long enough that it should now be shrunk.
+    pub(crate) fn end_frame(&mut self) -> bool {
+        let target_width = self.bucket(self.width);
+        let target_height = self.bucket(self.height);
+        if target_width == self.allocated_width && target_height == self.allocated_height {
+            self.oversized_frames = 0;
+            return false;
+        }
+
+        self.oversized_frames += 1;
+        if self.oversized_frames < SHRINK_DELAY_FRAMES {
+            return false;
+        }
+
+        self.allocated_width = target_width;
+        self.allocated_height = target_height;
+        self.oversized_frames = 0;
+        true
+    }
+
+    fn bucket(&self, size: u32) -> u32 {
+        let size = size.max(1);
+        if !self.resized {
+            return size;
+        }
+        size.next_multiple_of(BUCKET_SIZE)
+            .min(self.max_dimension)
+            .max(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tracker which has been resized to `width` x `height`, so uses buckets
+    fn resized(width: u32, height: u32, max_dimension: u32) -> ResizeTracker {
+        let mut tracker = ResizeTracker::new(1, 1, max_dimension);
+        tracker.resize(width, height);
+        tracker
+    }
+
+    #[test]
+    fn exact_until_resized() {
+        let mut tracker = ResizeTracker::new(64, 64, 8192);
+        assert_eq!(tracker.allocated_size(), (64, 64));
+        assert!(!tracker.resize(64, 64));
+        for _ in 0..SHRINK_DELAY_FRAMES {
+            assert!(!tracker.end_frame());
+        }
+        assert_eq!(tracker.allocated_size(), (64, 64));
+
+        assert_eq!(ResizeTracker::new(0, 0, 8192).allocated_size(), (1, 1));
+    }
+
+    #[test]
+    fn rounds_up_to_bucket() {
+        assert_eq!(resized(300, 100, 8192).allocated_size(), (512, 256));
+        assert_eq!(resized(512, 256, 8192).allocated_size(), (512, 256));
+        assert_eq!(resized(0, 300, 8192).allocated_size(), (256, 512));
+    }
+
+    #[test]
+    fn clamps_to_max_dimension() {
+        assert_eq!(resized(900, 100, 1000).allocated_size(), (1000, 256));
+        // Sizes beyond the maximum are passed through (and rejected by the device)
+        assert_eq!(resized(1200, 100, 1000).allocated_size(), (1200, 256));
+    }
+
+    #[test]
+    fn grows_only_when_needed() {
+        let mut tracker = resized(300, 100, 8192);
+        assert!(!tracker.resize(500, 200));
+        assert_eq!(tracker.allocated_size(), (512, 256));
+
+        assert!(tracker.resize(600, 200));
+        assert_eq!(tracker.allocated_size(), (768, 256));
+    }
+
+    #[test]
+    fn shrinks_after_delay() {
+        let mut tracker = ResizeTracker::new(600, 600, 8192);
+        assert!(!tracker.resize(100, 100));
+        for _ in 1..SHRINK_DELAY_FRAMES {
+            assert!(!tracker.end_frame());
+        }
+        assert_eq!(tracker.allocated_size(), (600, 600));
+
+        assert!(tracker.end_frame());
+        assert_eq!(tracker.allocated_size(), (256, 256));
+        assert!(!tracker.end_frame());
+    }
+
+    #[test]
+    fn resize_restarts_shrink_delay() {
+        let mut tracker = ResizeTracker::new(600, 600, 8192);
+        tracker.resize(100, 100);
+        for _ in 1..SHRINK_DELAY_FRAMES {
+            tracker.end_frame();
+        }
+        tracker.resize(120, 120);
+        assert!(!tracker.end_frame());
+        assert_eq!(tracker.allocated_size(), (600, 600));
+    }
+}
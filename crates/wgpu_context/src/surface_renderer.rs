@@ -1,8 +1,11 @@
-use crate::{DeviceHandle, WgpuContextError, util::create_texture};
+use crate::{
+    DeviceHandle, WgpuContextError, blit::RegionBlitter, resize::ResizeTracker,
+    util::create_texture,
+};
 use wgpu::{
     CommandEncoderDescriptor, CompositeAlphaMode, Device, PresentMode, Queue, Surface,
-    SurfaceConfiguration, SurfaceTexture, TextureFormat, TextureUsages, TextureView,
-    TextureViewDescriptor, util::TextureBlitter,
+    SurfaceConfiguration, SurfaceError, SurfaceTexture, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor,
 };
 
 #[derive(Clone)]
@@ -14,8 +17,11 @@ pub struct TextureConfiguration {
 pub struct SurfaceRendererConfiguration {
     /// The usage of the swap chain. The only usage guaranteed to be supported is [`TextureUsages::RENDER_ATTACHMENT`].
     pub usage: TextureUsages,
-    /// The texture format of the swap chain. The only formats that are guaranteed are
+    /// The texture formats that may be used for the swap chain. The only formats that are guaranteed are
     /// [`TextureFormat::Bgra8Unorm`] and [`TextureFormat::Bgra8UnormSrgb`].
+    ///
+    /// If any of the formats are extended range formats (see [`SurfaceColorSpace`]) which the surface
+    /// supports then the first of them is used. Otherwise the format that the surface prefers most is used.
     pub formats: Vec<TextureFormat>,
     /// Width of the swap chain. Must be the same size as the surface, and nonzero.
     ///
@@ -66,14 +72,150 @@ pub struct SurfaceRendererConfiguration {
     pub view_formats: Vec<TextureFormat>,
 }
 
+/// The color space in which the presentation engine interprets the contents of a surface.
+///
+/// wgpu determines the color space of a surface from its texture format, so selecting a color space
+/// amounts to selecting a set of surface formats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SurfaceColorSpace {
+    /// Standard dynamic range sRGB (8-bit `Unorm` or `UnormSrgb` formats)
+    #[default]
+    Srgb,
+    /// Linear extended-range sRGB (scRGB) using a [`TextureFormat::Rgba16Float`] surface.
+    ///
+    /// Note that this only changes the format of the surface. The intermediate texture that is rendered to
+    /// (and blitted onto the surface) is still 8-bit sRGB, so the presented image contains no values outside
+    /// of the `[0, 1]` range: there is no extra brightness (HDR) or gamut. Supported by the Metal, DX12 and
+    /// Vulkan (`VK_EXT_swapchain_colorspace`) backends.
+    ExtendedSrgbLinear,
+}
+
+impl SurfaceColorSpace {
+    /// The color space used by surfaces with the specified format
+    pub fn from_format(format: TextureFormat) -> Self {
+        match format {
+            TextureFormat::Rgba16Float => Self::ExtendedSrgbLinear,
+            _ => Self::Srgb,
+        }
+    }
+
+    /// The surface formats to request for this color space (see [`SurfaceRendererConfiguration::formats`]).
+    ///
+    /// `sdr_formats` are included as a fallback for surfaces which don't support the color space.
+    pub fn surface_formats(self, sdr_formats: &[TextureFormat]) -> Vec<TextureFormat> {
+        let mut formats = match self {
+            Self::Srgb => Vec::new(),
+            Self::ExtendedSrgbLinear => vec![TextureFormat::Rgba16Float],
+        };
+        for format in sdr_formats {
+            if !formats.contains(format) {
+                formats.push(*format);
+            }
+        }
+        formats
+    }
+}
+
 struct IntermediateTextureStuff {
     pub config: TextureConfiguration,
     // TextureView for the intermediate Texture which we sometimes render to because compute shaders
     // cannot always render directly to surfaces. Since WGPU 26, the underlying Texture can be accessed
     // from the TextureView so we don't need to store both.
+    //
+    // Once resized, the texture is over-allocated (see `ResizeTracker`), so only its top-left region is rendered to.
     pub texture_view: TextureView,
-    // Blitter for blitting from the intermediate texture to the surface.
-    pub blitter: TextureBlitter,
+    pub allocation: ResizeTracker,
+    // Blitter for blitting from the used region of the intermediate texture to the surface.
+    pub blitter: RegionBlitter,
+    // The intermediate texture holds sRGB-encoded values. When the surface expects linear values this is set,
+    // and the intermediate texture is sampled through an sRGB view so that the values are decoded during the blit.
+    pub decode_srgb: bool,
+}
+
+impl IntermediateTextureStuff {
+    fn new(
+        config: TextureConfiguration,
+        width: u32,
+        height: u32,
+        surface_format: TextureFormat,
+        device: &Device,
+    ) -> Self {
+        let decode_srgb =
+            SurfaceColorSpace::from_format(surface_format) == SurfaceColorSpace::ExtendedSrgbLinear;
+        let allocation =
+            ResizeTracker::new(width, height, device.limits().max_texture_dimension_2d);
+        let (allocated_width, allocated_height) = allocation.allocated_size();
+        Self {
+            texture_view: Self::create_texture_view(
+                allocated_width,
+                allocated_height,
+                &config,
+                decode_srgb,
+                device,
+            ),
+            allocation,
+            // sRGB surfaces are blitted to through a non-sRGB view, as the values in the intermediate
+            // texture are already sRGB-encoded.
+            blitter: RegionBlitter::new(device, surface_format.remove_srgb_suffix()),
+            config,
+            decode_srgb,
+        }
+    }
+
+    fn create_texture_view(
+        width: u32,
+        height: u32,
+        config: &TextureConfiguration,
+        decode_srgb: bool,
+        device: &Device,
+    ) -> TextureView {
+        let view_formats: &[TextureFormat] = if decode_srgb {
+            &[TextureFormat::Rgba8UnormSrgb]
+        } else {
+            &[]
+        };
+        create_texture(
+            width,
+            height,
+            TextureFormat::Rgba8Unorm,
+            config.usage,
+            view_formats,
+            device,
+        )
+    }
+
+    fn resize(&mut self, width: u32, height: u32, device: &Device) {
+        if self.allocation.resize(width, height) {
+            self.reallocate(device);
+        }
+    }
+
+    fn end_frame(&mut self, device: &Device) {
+        if self.allocation.end_frame() {
+            self.reallocate(device);
+        }
+    }
+
+    fn reallocate(&mut self, device: &Device) {
+        let (width, height) = self.allocation.allocated_size();
+        self.texture_view =
+            Self::create_texture_view(width, height, &self.config, self.decode_srgb, device);
+    }
+
+    /// The view of the intermediate texture that the blit samples from
+    fn blit_source_view(&self) -> TextureView {
+        if !self.decode_srgb {
+            return self.texture_view.clone();
+        }
+        self.texture_view
+            .texture()
+            .create_view(&TextureViewDescriptor {
+                format: Some(TextureFormat::Rgba8UnormSrgb),
+                // The sRGB format doesn't support storage binding, so the view is restricted to sampling
+                usage: Some(TextureUsages::TEXTURE_BINDING),
+                ..Default::default()
+            })
+    }
 }
 
 /// Combination of surface and its configuration.
@@ -110,34 +252,48 @@ impl<'s> SurfaceRenderer<'s> {
     ) -> Result<SurfaceRenderer<'w>, WgpuContextError> {
         // Convert SurfaceRendererConfiguration to SurfaceConfiguration.
         // The difference is that `format` is a Vec in SurfaceRendererConfiguration and a single value in SurfaceConfiguration
+        let supported_formats = surface.get_capabilities(&device_handle.adapter).formats;
+        let requested_formats = &surface_renderer_config.formats;
+        let extended_format = requested_formats.iter().copied().find(|format| {
+            SurfaceColorSpace::from_format(*format) != SurfaceColorSpace::Srgb
+                && supported_formats.contains(format)
+        });
+        let format = extended_format
+            .or_else(|| {
+                supported_formats
+                    .into_iter()
+                    .find(|format| requested_formats.contains(format))
+            })
+            .ok_or(WgpuContextError::UnsupportedSurfaceFormat)?;
+
+        // sRGB surfaces are blitted to through a non-sRGB view (see `IntermediateTextureStuff`)
+        let mut view_formats = surface_renderer_config.view_formats;
+        if intermediate_texture_config.is_some()
+            && format.is_srgb()
+            && !view_formats.contains(&format.remove_srgb_suffix())
+        {
+            view_formats.push(format.remove_srgb_suffix());
+        }
+
         let surface_config = SurfaceConfiguration {
             usage: surface_renderer_config.usage,
-            format: surface
-                .get_capabilities(&device_handle.adapter)
-                .formats
-                .into_iter()
-                .find(|it| surface_renderer_config.formats.contains(it))
-                .ok_or(WgpuContextError::UnsupportedSurfaceFormat)?,
+            format,
             width: surface_renderer_config.width,
             height: surface_renderer_config.height,
             present_mode: surface_renderer_config.present_mode,
             desired_maximum_frame_latency: surface_renderer_config.desired_maximum_frame_latency,
             alpha_mode: surface_renderer_config.alpha_mode,
-            view_formats: surface_renderer_config.view_formats,
+            view_formats,
         };
 
         let intermediate_texture = intermediate_texture_config.map(|texture_config| {
-            Box::new(IntermediateTextureStuff {
-                config: texture_config.clone(),
-                texture_view: create_texture(
-                    surface_renderer_config.width,
-                    surface_renderer_config.height,
-                    TextureFormat::Rgba8Unorm,
-                    texture_config.usage,
-                    &device_handle.device,
-                ),
-                blitter: TextureBlitter::new(&device_handle.device, surface_config.format),
-            })
+            Box::new(IntermediateTextureStuff::new(
+                texture_config,
+                surface_renderer_config.width,
+                surface_renderer_config.height,
+                format,
+                &device_handle.device,
+            ))
         });
 
         let surface = SurfaceRenderer {
@@ -160,17 +316,11 @@ impl<'s> SurfaceRenderer<'s> {
     }
 
     /// Resizes the surface to the new dimensions.
+    ///
+    /// The intermediate texture (if any) is only reallocated if it is too small for the new dimensions.
     pub fn resize(&mut self, width: u32, height: u32) {
-        // TODO: Use clever resize semantics to avoid thrashing the memory allocator during a resize
-        // especially important on metal.
         if let Some(intermediate_texture_stuff) = &mut self.intermediate_texture {
-            intermediate_texture_stuff.texture_view = create_texture(
-                width,
-                height,
-                TextureFormat::Rgba8Unorm,
-                intermediate_texture_stuff.config.usage,
-                &self.device_handle.device,
-            );
+            intermediate_texture_stuff.resize(width, height, &self.device_handle.device);
         }
         self.config.width = width;
         self.config.height = height;
@@ -187,38 +337,75 @@ impl<'s> SurfaceRenderer<'s> {
             .configure(&self.device_handle.device, &self.config);
     }
 
+    /// The color space in which the contents of the surface are interpreted
+    pub fn color_space(&self) -> SurfaceColorSpace {
+        SurfaceColorSpace::from_format(self.config.format)
+    }
+
+    /// Whether the device used to render to this surface has been lost.
+    ///
+    /// If so, the surface must be recreated (see [`crate::WGPUContext::create_surface`]). Once the
+    /// context has replaced the lost device this returns `false`, so renderers sharing a context with
+    /// other renderers should use [`crate::WGPUContext::is_device_lost`] instead.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_handle.is_lost()
+    }
+
     pub fn current_surface_texture(&self) -> SurfaceTexture {
-        self.surface
-            .get_current_texture()
+        self.try_current_surface_texture()
             .expect("failed to get surface texture")
     }
 
+    /// Gets the next texture to be presented to the surface.
+    ///
+    /// If the surface is outdated (e.g. because the window was resized concurrently) then it is
+    /// reconfigured and acquisition is retried once. Any other error (including [`SurfaceError::Lost`],
+    /// which requires the surface to be recreated) is returned to the caller.
+    pub fn try_current_surface_texture(&self) -> Result<SurfaceTexture, SurfaceError> {
+        match self.surface.get_current_texture() {
+            Err(SurfaceError::Outdated) => {
+                self.configure();
+                self.surface.get_current_texture()
+            }
+            result => result,
+        }
+    }
+
+    /// The view to render to. If there is an intermediate texture then it may be larger than the surface,
+    /// in which case only the top-left region with the same size as the surface will be presented.
     pub fn target_texture_view(&self) -> TextureView {
         match &self.intermediate_texture {
             Some(intermediate_texture) => intermediate_texture.texture_view.clone(),
-            None => {
-                let surface_texture = self
-                    .surface
-                    .get_current_texture()
-                    .expect("failed to get surface texture");
-                surface_texture
-                    .texture
-                    .create_view(&TextureViewDescriptor::default())
-            }
+            None => self
+                .current_surface_texture()
+                .texture
+                .create_view(&TextureViewDescriptor::default()),
         }
     }
 
-    pub fn maybe_blit_and_present(&self) {
-        let surface_texture = self
-            .surface
-            .get_current_texture()
-            .expect("failed to get surface texture");
+    pub fn maybe_blit_and_present(&mut self) {
+        self.try_maybe_blit_and_present()
+            .expect("failed to get surface texture")
+    }
+
+    /// Blits from the intermediate texture (if any) to the surface and presents it.
+    ///
+    /// See [`SurfaceRenderer::try_current_surface_texture`] for the errors that may be returned.
+    pub fn try_maybe_blit_and_present(&mut self) -> Result<(), SurfaceError> {
+        let surface_texture = self.try_current_surface_texture()?;
 
         if let Some(its) = &self.intermediate_texture {
             self.blit_from_intermediate_texture_to_surface(&surface_texture, its);
         }
 
         surface_texture.present();
+
+        // Shrink the intermediate texture if it has been larger than needed for a while
+        if let Some(its) = &mut self.intermediate_texture {
+            its.end_frame(&self.device_handle.device);
+        }
+
+        Ok(())
     }
 
     /// Blit from the intermediate texture to the surface texture
@@ -227,15 +414,6 @@ impl<'s> SurfaceRenderer<'s> {
         surface_texture: &SurfaceTexture,
         intermediate_texture_stuff: &IntermediateTextureStuff,
     ) {
-        // TODO: verify that handling of SurfaceError::Outdated is no longer required
-        //
-        // let surface_texture = match state.surface.surface.get_current_texture() {
-        //     Ok(surface) => surface,
-        //     // When resizing too aggresively, the surface can get outdated (another resize) before being rendered into
-        //     Err(SurfaceError::Outdated) => return,
-        //     Err(_) => panic!("failed to get surface texture"),
-        // };
-
         // Perform the copy
         // (TODO: Does it improve throughput to acquire the surface after the previous texture render has happened?)
         let mut encoder =
@@ -248,10 +426,11 @@ impl<'s> SurfaceRenderer<'s> {
         intermediate_texture_stuff.blitter.copy(
             &self.device_handle.device,
             &mut encoder,
-            &intermediate_texture_stuff.texture_view,
-            &surface_texture
-                .texture
-                .create_view(&TextureViewDescriptor::default()),
+            &intermediate_texture_stuff.blit_source_view(),
+            &surface_texture.texture.create_view(&TextureViewDescriptor {
+                format: Some(self.config.format.remove_srgb_suffix()),
+                ..Default::default()
+            }),
         );
         self.device_handle.queue.submit([encoder.finish()]);
     }
@@ -0,0 +1,108 @@
+use crate::DeviceHandle;
+use wgpu::{
+    Device, Extent3d, Queue, Texture, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, TextureView, TextureViewDescriptor,
+};
+
+#[derive(Clone, Debug)]
+pub struct TextureRendererConfig {
+    pub width: u32,
+    pub height: u32,
+    /// Additional usages for the texture. `STORAGE_BINDING`, `TEXTURE_BINDING` and `COPY_SRC`
+    /// are always included.
+    pub usage: TextureUsages,
+}
+
+/// Utility struct for rendering to a texture which stays on the GPU.
+///
+/// The texture has the `Rgba8Unorm` format and can be rendered to using compute shaders (`STORAGE_BINDING`),
+/// sampled by other wgpu code (`TEXTURE_BINDING`), and copied from (`COPY_SRC`), which allows it to be
+/// registered as an image with a Vello renderer.
+///
+/// The texture is recreated when the renderer is resized, so clones of it held elsewhere (including images
+/// registered with a renderer) must be refreshed using [`TextureRenderer::texture`] after resizing.
+pub struct TextureRenderer {
+    // The device and queue for rendering to the texture
+    pub dev_id: usize,
+    pub device_handle: DeviceHandle,
+
+    config: TextureRendererConfig,
+    texture: Texture,
+}
+
+impl std::fmt::Debug for TextureRenderer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TextureRenderer")
+            .field("dev_id", &self.dev_id)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl TextureRenderer {
+    /// Creates a new texture renderer with the specified dimensions.
+    pub fn new(config: TextureRendererConfig, device_handle: DeviceHandle, dev_id: usize) -> Self {
+        let texture = Self::create_texture(&config, &device_handle.device);
+        Self {
+            dev_id,
+            device_handle,
+            config,
+            texture,
+        }
+    }
+
+    fn create_texture(config: &TextureRendererConfig, device: &Device) -> Texture {
+        device.create_texture(&TextureDescriptor {
+            label: Some("TextureRenderer texture"),
+            size: Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: config.usage
+                | TextureUsages::STORAGE_BINDING
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device_handle.device
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.device_handle.queue
+    }
+
+    pub fn size(&self) -> Extent3d {
+        Extent3d {
+            width: self.config.width,
+            height: self.config.height,
+            depth_or_array_layers: 1,
+        }
+    }
+
+    /// Resizes the texture. The texture is only recreated if the dimensions have changed.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == self.config.width && height == self.config.height {
+            return;
+        }
+        self.config.width = width;
+        self.config.height = height;
+        self.texture = Self::create_texture(&self.config, &self.device_handle.device);
+    }
+
+    /// The texture being rendered to. Cloning a `wgpu::Texture` is cheap as it is reference counted.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn target_texture_view(&self) -> TextureView {
+        self.texture.create_view(&TextureViewDescriptor::default())
+    }
+}
@@ -38,6 +38,7 @@ pub(crate) fn create_texture(
     height: u32,
     format: TextureFormat,
     usage: TextureUsages,
+    view_formats: &[TextureFormat],
     device: &Device,
 ) -> TextureView {
     let texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -52,7 +53,7 @@ pub(crate) fn create_texture(
         dimension: wgpu::TextureDimension::D2,
         usage,
         format,
-        view_formats: &[],
+        view_formats,
     });
 
     texture.create_view(&wgpu::TextureViewDescriptor::default())
@@ -1,6 +1,6 @@
 use anyrender::{PaintScene, WindowRenderer};
 use anyrender_skia::SkiaWindowRenderer;
-use anyrender_vello::VelloWindowRenderer;
+use anyrender_vello::{FramePacing, VelloRendererOptions, VelloWindowRenderer};
 use anyrender_vello_cpu::VelloCpuWindowRenderer;
 use anyrender_vello_hybrid::VelloHybridWindowRenderer;
 use bunny::BunnyManager;
@@ -277,9 +277,12 @@ impl ApplicationHandler for App {
                             });
                         }
                         Renderer::Hybrid(_) => {
-                            self.set_backend(VelloWindowRenderer::new(), event_loop, |r| {
-                                Renderer::Gpu(Box::new(r))
-                            });
+                            let renderer =
+                                VelloWindowRenderer::with_options(VelloRendererOptions {
+                                    frame_pacing: FramePacing::Pipelined,
+                                    ..Default::default()
+                                });
+                            self.set_backend(renderer, event_loop, |r| Renderer::Gpu(Box::new(r)));
                         }
                         Renderer::Gpu(_) => {
                             self.set_backend(SkiaWindowRenderer::new(), event_loop, |r| {